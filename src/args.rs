@@ -19,15 +19,21 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
-use crate::disktest::{DisktestQuiet, DtStreamType};
-use crate::seed::gen_seed_string;
-use crate::util::parsebytes;
+use crate::bench::BENCH_ALGORITHMS;
+use crate::metadata::RunMetadata;
+use crate::seed::{gen_seed_string, prompt_seed, read_seed_env, read_seed_file, read_seed_stdin};
 use anyhow as ah;
 use clap::builder::ValueParser;
 use clap::error::ErrorKind::{DisplayHelp, DisplayVersion};
-use clap::{value_parser, Arg, ArgAction, Command};
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgGroup, Command};
+use disktest::{
+    parsebytes, Disktest, DisktestError, DisktestQuiet, DtCompressRatio, DtDedupRatio,
+    DtKeySchedule, DtKeySet, DtSecret, DtSeedId, DtStreamLayout, DtStreamType,
+};
 use std::ffi::OsString;
 use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
 /// Length of the generated seed.
 const DEFAULT_GEN_SEED_LEN: usize = 40;
@@ -49,6 +55,18 @@ disktest --write --verify -j0 /dev/sdc";
 const EXAMPLE: &str = "\
 disktest --write --verify -j0 \\\\.\\E:";
 
+const EXIT_STATUS: &str = "\
+Exit status:
+  0  Success.
+  1  Any other error (e.g. the device could not be opened).
+  2  Invalid command line arguments.
+  3  Data mismatch during verification.
+  4  Read error.
+  5  Write error.
+  6  Out of disk space before the requested number of bytes was written.
+  7  Aborted by signal.
+  8  Random number generator failure.";
+
 const HELP_DEVICE: &str = "\
 Device node of the disk or file path to access.
 ";
@@ -76,10 +94,66 @@ random sequence.
 If both --write and --verify are specified, then the device
 will first be written and then be verified with the same seed.";
 
+const HELP_KEEP_GOING: &str = "\
+Do not abort verification at the first data mismatch.
+Continue verifying until the end and print a list of all
+corrupted regions and the affected sectors at the end.
+If there are too many regions, neighboring regions are merged.";
+
+const HELP_SKIP_READ_ERRORS: &str = "\
+Do not abort verification at a read error.
+The failing chunk is read again in smaller pieces down to single sectors
+to find the exact unreadable sectors. These sectors are skipped and
+verification continues until the end. A list of all unreadable sectors
+is printed at the end.";
+
+const HELP_HEXDUMP: &str = "\
+Print a side-by-side hexdump of the expected and the actual data
+around the first mismatching bytes, if verification fails.";
+
+const HELP_DUMP_DIR: &str = "\
+Save the raw sectors read from the disk and the expected data
+of mismatching regions to files in this directory for later analysis.
+The files are named after the absolute byte offset of the saved data.";
+
+const HELP_REPORT: &str = "\
+Write a machine readable JSON report of the run to this file.
+The report contains the test parameters, the statistics of each
+write/verify phase and all errors with their offsets.";
+
+const HELP_CHECKPOINT: &str = "\
+Periodically record the progress of the run in this checkpoint file.
+An interrupted run can be continued with --resume.";
+
+const HELP_RESUME: &str = "\
+Resume an interrupted run from the --checkpoint file.
+All other options, including the --seed, must be the same as
+in the interrupted run.";
+
+const HELP_METADATA: &str = "\
+Record the parameters of a write run in this file: algorithm, layout,
+key schedule, threads, inverted pattern, seek, bytes, passes, a fingerprint of the seed,
+the time and the host name. The seed itself is not recorded.
+In verify-only mode the parameters are read from this file instead.
+Parameters given on the command line must match the recorded ones.";
+
+const HELP_BENCH: &str = "\
+Measure the throughput of the random number generators without any disk I/O
+and exit. All algorithms are measured, or only the one selected with --algorithm.
+Each algorithm is measured with 1, 2, 4, ... threads up to --threads.
+By default up to the number of online CPUs is measured.
+The device is optional. If it is given, its read rate is probed
+and the smallest number of threads that is faster than the device is recommended.";
+
+const HELP_BENCH_TARGET: &str = "\
+Recommend the smallest number of threads that is faster than this rate
+(in bytes per second) in --bench mode, instead of probing the device.";
+
 const HELP_SEEK: &str = "\
 Seek to the specified byte position on disk
 before starting the write/verify operation. This skips the specified
 amount of bytes on the disk and also fast forwards the random number generator.
+The position must be a multiple of the sector size.
 ";
 
 const HELP_BYTES: &str = "\
@@ -91,25 +165,112 @@ const HELP_ALGORITHM: &str = "\
 Select the random number generator algorithm.
 ChaCha12 and ChaCha8 are less cryptographically secure than ChaCha20, but
 faster. CRC is even faster, but not cryptographically secure at all.
+AES128CTR and AES256CTR are cryptographically secure and very fast on CPUs
+with AES instructions (e.g. AES-NI), which are detected automatically.
+SPLITMIX is the fastest generator, but not cryptographically secure at all.
+TAGGED is ChaCha20 with a tag at the start of every 512 byte block.
+The tag holds the run ID, the block address and the pass number.
+On a mismatch the tag tells whether a block holds data meant for another
+address (misdirected write) or stale data from another pass or run.
+
+The following deterministic test patterns are available, too.
+They do not use the seed:
+ZEROS: All bits zero.
+ONES: All bits one.
+CHECKER55, CHECKERAA: 0x55 or 0xAA checkerboard.
+WALKING1: A single one bit walking through each byte (0x01, 0x02, 0x04, ...).
+WALKING0: A single zero bit walking through each byte (0xFE, 0xFD, 0xFB, ...).
+PATTERN:HEX: A repeating byte pattern of up to 32 bytes (e.g. PATTERN:DEADBEEF).
+Structured patterns detect stuck-at bits and coupling faults between
+adjacent cells better than random data.
+";
+
+const HELP_LAYOUT: &str = "\
+Select the layout of the random number stream.
+INTERLEAVED: Every thread generates its own stream and the streams are
+interleaved chunk by chunk. The stream depends on the number of threads.
+INDEXED: Every chunk only depends on the seed and the chunk index.
+The stream is the same for any number of threads, so it can be
+written and verified with different --threads values.
+It is equal to the INTERLEAVED stream of a single thread.
+";
+
+const HELP_COMPRESS_RATIO: &str = "\
+Make the generated data compressible by the given ratio
+(uncompressed size / compressed size, e.g. 2.5).
+Every 4 kiB block then consists of random data and one run of a repeated byte.
+The position and the value of the run are derived from the seed,
+so the data stays reproducible for verification.
+This can be used to test disks that compress data internally.
+The default 1 generates incompressible data. Not supported by TAGGED.
+";
+
+const HELP_DEDUP_RATIO: &str = "\
+Make the generated data deduplicable by the given ratio
+(written data / unique data, e.g. 2 means that half of the chunks
+repeat the data of an earlier chunk).
+Which chunks are duplicates and which chunk they repeat is derived
+from the seed, so verification knows the data of every chunk.
+This can be used to test storage with deduplication and to detect
+deduplication bugs that return the wrong block.
+The default 1 generates no duplicates. Not supported by TAGGED.
+";
+
+const HELP_KEY_SCHEDULE: &str = "\
+Select the key schedule, that derives the generator keys from the seed.
+V1: One slow key derivation per thread. The startup takes longer
+with many threads. This is compatible with all disktest versions.
+V2: One slow key derivation of a master key from the seed.
+The keys of all threads are cheaply expanded from the master key.
+Data written with V2 must also be verified with V2.
 ";
 
 const HELP_SEED: &str = "\
 The seed to use for random number stream generation.
 The seed may be any random string (e.g. a long passphrase).
 If no seed is given, then a secure random seed will be generated
-and also printed to the console.";
+and also printed to the console.
+Note that the command line is visible to other users (e.g. in ps)
+and may be recorded in the shell history. Prefer --seed-file,
+--seed-stdin, --seed-env or --seed-prompt for secret seeds.";
+
+const HELP_SEED_FILE: &str = "\
+Read the seed from the first line of FILE.";
+
+const HELP_SEED_STDIN: &str = "\
+Read the seed from the first line of stdin.";
+
+const HELP_SEED_ENV: &str = "\
+Read the seed from the environment variable VAR.";
+
+const HELP_SEED_PROMPT: &str = "\
+Interactively prompt for the seed.
+The seed is not echoed to the terminal.";
+
+const HELP_GEN_SEED_FILE: &str = "\
+Write the generated seed to the new FILE instead of printing it
+to the console. The file is only readable by its owner.
+Use it with --seed-file for subsequent --verify.";
 
 const HELP_INVERT_PATTERN: &str = "\
 Invert the bit pattern generated by the random number generator.
 This can be useful, if a second write/verify run with a strictly
 inverted test bit pattern is desired.";
 
+const HELP_PASSES: &str = "\
+Number of write/verify passes.
+Each pass first writes and then verifies the whole range.
+Every even-numbered pass uses the inverted pattern of the pass before it.
+Passes 3 and 4 use the seed SEED#pass2, passes 5 and 6 use SEED#pass3 and so on.
+All passes must succeed. Requires --write.";
+
 const HELP_THREADS: &str = "\
 The number of CPUs to use.
 The special value 0 will select the maximum number of online CPUs in the
 system. If the number of threads is equal to number of CPUs it is optimal
 for performance. The number of threads must be equal during corresponding
 verify and write mode runs. Otherwise the verification will fail.
+This does not apply to --layout INDEXED.
 ";
 
 const HELP_QUIET: &str = "\
@@ -122,19 +283,79 @@ Quiet level:
 
 /// All command line arguments.
 pub struct Args {
+    /// Empty in --bench mode without a device.
     pub device: PathBuf,
     pub write: bool,
     pub verify: bool,
+    pub keep_going: bool,
+    pub skip_read_errors: bool,
+    pub hexdump: bool,
+    pub dump_dir: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub metadata: Option<PathBuf>,
+    pub bench: bool,
+    pub bench_target: Option<u64>,
+    pub bench_algorithms: Vec<DtStreamType>,
     pub seek: u64,
     pub max_bytes: u64,
     pub algorithm: DtStreamType,
-    pub seed: String,
+    pub layout: DtStreamLayout,
+    pub compress_ratio: DtCompressRatio,
+    pub dedup_ratio: DtDedupRatio,
+    pub key_schedule: DtKeySchedule,
+    pub seed: DtSecret,
+    /// The generator keys of the seed, once they are derived.
+    keys: OnceLock<Arc<DtKeySet>>,
+    /// The identifiers of the seed, once they are derived.
+    seed_id: OnceLock<DtSeedId>,
     pub user_seed: bool,
+    /// Write the generated seed to this file instead of printing it.
+    pub gen_seed_file: Option<PathBuf>,
     pub invert_pattern: bool,
+    pub passes: u32,
     pub threads: usize,
     pub quiet: DisktestQuiet,
 }
 
+impl Args {
+    /// Get the generator keys of the seed.
+    /// They are derived on the first call
+    /// and are shared by the seed identifiers and the first pass.
+    pub fn key_set(&self) -> &Arc<DtKeySet> {
+        self.keys.get_or_init(|| {
+            let count = self
+                .layout
+                .key_count(Disktest::resolve_nr_threads(self.threads));
+            Arc::new(DtKeySet::derive(&self.seed, self.key_schedule, count))
+        })
+    }
+
+    /// Get the identifiers of the seed.
+    /// They are expanded from the generator keys on the first call.
+    pub fn seed_id(&self) -> &DtSeedId {
+        self.seed_id.get_or_init(|| DtSeedId::new(self.key_set()))
+    }
+
+    /// Get the fingerprint of the seed and the stream parameters of the run.
+    pub fn stream_fingerprint(&self) -> String {
+        // The indexed layout does not depend on the number of threads.
+        let stride = self
+            .layout
+            .stride(Disktest::resolve_nr_threads(self.threads));
+        self.seed_id().stream_fingerprint(&[
+            &self.algorithm,
+            &self.layout,
+            &self.compress_ratio,
+            &self.dedup_ratio,
+            &self.key_schedule,
+            &stride,
+            &self.invert_pattern,
+        ])
+    }
+}
+
 /// Parse all command line arguments and put them into a structure.
 pub fn parse_args<I, T>(args: I) -> ah::Result<Args>
 where
@@ -146,10 +367,11 @@ where
 
     let args = Command::new("disktest")
         .about(about)
+        .after_help(EXIT_STATUS)
         .arg(
             Arg::new("device")
                 .index(1)
-                .required(true)
+                .required_unless_present("bench")
                 .value_parser(value_parser!(PathBuf))
                 .help(help_device),
         )
@@ -167,6 +389,75 @@ where
                 .action(ArgAction::SetTrue)
                 .help(HELP_VERIFY),
         )
+        .arg(
+            Arg::new("keep-going")
+                .long("keep-going")
+                .short('k')
+                .action(ArgAction::SetTrue)
+                .help(HELP_KEEP_GOING),
+        )
+        .arg(
+            Arg::new("skip-read-errors")
+                .long("skip-read-errors")
+                .action(ArgAction::SetTrue)
+                .help(HELP_SKIP_READ_ERRORS),
+        )
+        .arg(
+            Arg::new("hexdump")
+                .long("hexdump")
+                .action(ArgAction::SetTrue)
+                .help(HELP_HEXDUMP),
+        )
+        .arg(
+            Arg::new("dump-dir")
+                .long("dump-dir")
+                .value_name("DIR")
+                .value_parser(value_parser!(PathBuf))
+                .help(HELP_DUMP_DIR),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help(HELP_REPORT),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help(HELP_CHECKPOINT),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(ArgAction::SetTrue)
+                .requires("checkpoint")
+                .help(HELP_RESUME),
+        )
+        .arg(
+            Arg::new("metadata")
+                .long("metadata")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help(HELP_METADATA),
+        )
+        .arg(
+            Arg::new("bench")
+                .long("bench")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["write", "verify", "resume"])
+                .help(HELP_BENCH),
+        )
+        .arg(
+            Arg::new("bench-target")
+                .long("bench-target")
+                .value_name("BYTES")
+                .value_parser(ValueParser::new(parsebytes))
+                .requires("bench")
+                .help(HELP_BENCH_TARGET),
+        )
         .arg(
             Arg::new("seek")
                 .long("seek")
@@ -191,10 +482,41 @@ where
                 .short('A')
                 .value_name("ALG")
                 .default_value("CHACHA20")
-                .value_parser(["CHACHA8", "CHACHA12", "CHACHA20", "CRC"])
-                .ignore_case(true)
+                .value_parser(ValueParser::new(|s: &str| s.parse::<DtStreamType>()))
                 .help(HELP_ALGORITHM),
         )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .value_name("LAYOUT")
+                .default_value("INTERLEAVED")
+                .value_parser(ValueParser::new(|s: &str| s.parse::<DtStreamLayout>()))
+                .help(HELP_LAYOUT),
+        )
+        .arg(
+            Arg::new("compress-ratio")
+                .long("compress-ratio")
+                .value_name("RATIO")
+                .default_value("1")
+                .value_parser(ValueParser::new(|s: &str| s.parse::<DtCompressRatio>()))
+                .help(HELP_COMPRESS_RATIO),
+        )
+        .arg(
+            Arg::new("dedup-ratio")
+                .long("dedup-ratio")
+                .value_name("RATIO")
+                .default_value("1")
+                .value_parser(ValueParser::new(|s: &str| s.parse::<DtDedupRatio>()))
+                .help(HELP_DEDUP_RATIO),
+        )
+        .arg(
+            Arg::new("key-schedule")
+                .long("key-schedule")
+                .value_name("VERSION")
+                .default_value("V1")
+                .value_parser(ValueParser::new(|s: &str| s.parse::<DtKeySchedule>()))
+                .help(HELP_KEY_SCHEDULE),
+        )
         .arg(
             Arg::new("seed")
                 .long("seed")
@@ -202,6 +524,46 @@ where
                 .value_name("SEED")
                 .help(HELP_SEED),
         )
+        .arg(
+            Arg::new("seed-file")
+                .long("seed-file")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help(HELP_SEED_FILE),
+        )
+        .arg(
+            Arg::new("seed-stdin")
+                .long("seed-stdin")
+                .action(ArgAction::SetTrue)
+                .help(HELP_SEED_STDIN),
+        )
+        .arg(
+            Arg::new("seed-env")
+                .long("seed-env")
+                .value_name("VAR")
+                .help(HELP_SEED_ENV),
+        )
+        .arg(
+            Arg::new("seed-prompt")
+                .long("seed-prompt")
+                .action(ArgAction::SetTrue)
+                .help(HELP_SEED_PROMPT),
+        )
+        .group(ArgGroup::new("seed-source").args([
+            "seed",
+            "seed-file",
+            "seed-stdin",
+            "seed-env",
+            "seed-prompt",
+        ]))
+        .arg(
+            Arg::new("gen-seed-file")
+                .long("gen-seed-file")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("seed-source")
+                .help(HELP_GEN_SEED_FILE),
+        )
         .arg(
             Arg::new("invert-pattern")
                 .long("invert-pattern")
@@ -209,13 +571,21 @@ where
                 .action(ArgAction::SetTrue)
                 .help(HELP_INVERT_PATTERN),
         )
+        .arg(
+            Arg::new("passes")
+                .long("passes")
+                .value_name("NUM")
+                .default_value("1")
+                .value_parser(value_parser!(u32).range(1..))
+                .help(HELP_PASSES),
+        )
         .arg(
             Arg::new("threads")
                 .long("threads")
                 .short('j')
                 .value_name("NUM")
                 .default_value("1")
-                .value_parser(value_parser!(u32).range(0_i64..=std::u16::MAX as i64 + 1))
+                .value_parser(value_parser!(u32).range(0_i64..=u16::MAX as i64 + 1))
                 .help(HELP_THREADS),
         )
         .arg(
@@ -239,7 +609,7 @@ where
                 }
                 _ => (),
             };
-            return Err(ah::format_err!("{}", e));
+            return Err(DisktestError::InvalidArguments.msg(e.to_string()));
         }
     };
 
@@ -254,81 +624,190 @@ where
         DisktestQuiet::NoWarn
     };
 
-    let device = args.get_one::<PathBuf>("device").unwrap().clone();
+    let device = args
+        .get_one::<PathBuf>("device")
+        .cloned()
+        .unwrap_or_default();
+
+    let bench = args.get_flag("bench");
+
+    let bench_target = args.get_one::<u64>("bench-target").copied();
 
     let write = args.get_flag("write");
     let mut verify = args.get_flag("verify");
-    if !write && !verify {
+    if !write && !verify && !bench {
         verify = true;
     }
 
+    let keep_going = args.get_flag("keep-going");
+
+    let skip_read_errors = args.get_flag("skip-read-errors");
+
+    let hexdump = args.get_flag("hexdump");
+
+    let dump_dir = args.get_one::<PathBuf>("dump-dir").cloned();
+
+    let report = args.get_one::<PathBuf>("report").cloned();
+
+    let checkpoint = args.get_one::<PathBuf>("checkpoint").cloned();
+
+    let resume = args.get_flag("resume");
+
+    let metadata = args.get_one::<PathBuf>("metadata").cloned();
+
     let seek = *args.get_one::<u64>("seek").unwrap();
 
     let max_bytes = *args.get_one::<u64>("bytes").unwrap();
 
-    let algorithm = match args
-        .get_one::<String>("algorithm")
-        .unwrap()
-        .to_ascii_uppercase()
-        .as_str()
-    {
-        "CHACHA8" => DtStreamType::ChaCha8,
-        "CHACHA12" => DtStreamType::ChaCha12,
-        "CHACHA20" => DtStreamType::ChaCha20,
-        "CRC" => DtStreamType::Crc,
-        _ => panic!("Invalid algorithm parameter."),
+    let algorithm = *args.get_one::<DtStreamType>("algorithm").unwrap();
+
+    let bench_algorithms = if !bench {
+        vec![]
+    } else if args.value_source("algorithm") == Some(ValueSource::CommandLine) {
+        vec![algorithm]
+    } else {
+        BENCH_ALGORITHMS.to_vec()
     };
 
-    let (seed, user_seed) = match args.get_one::<String>("seed") {
-        Some(x) => (x.clone(), true),
-        None => (gen_seed_string(DEFAULT_GEN_SEED_LEN), false),
+    let layout = *args.get_one::<DtStreamLayout>("layout").unwrap();
+
+    let compress_ratio = *args.get_one::<DtCompressRatio>("compress-ratio").unwrap();
+    if compress_ratio != DtCompressRatio::NONE && matches!(algorithm, DtStreamType::Tagged(_)) {
+        return Err(DisktestError::InvalidArguments
+            .msg("--compress-ratio is not supported by the TAGGED algorithm."));
+    }
+
+    let dedup_ratio = *args.get_one::<DtDedupRatio>("dedup-ratio").unwrap();
+    if dedup_ratio != DtDedupRatio::NONE && matches!(algorithm, DtStreamType::Tagged(_)) {
+        return Err(DisktestError::InvalidArguments
+            .msg("--dedup-ratio is not supported by the TAGGED algorithm."));
+    }
+
+    let key_schedule = *args.get_one::<DtKeySchedule>("key-schedule").unwrap();
+
+    let user_seed = if let Some(seed) = args.get_one::<String>("seed") {
+        Some(DtSecret::from(seed.as_str()))
+    } else if let Some(path) = args.get_one::<PathBuf>("seed-file") {
+        Some(read_seed_file(path)?)
+    } else if args.get_flag("seed-stdin") {
+        Some(read_seed_stdin()?)
+    } else if let Some(name) = args.get_one::<String>("seed-env") {
+        Some(read_seed_env(name)?)
+    } else if args.get_flag("seed-prompt") {
+        Some(prompt_seed()?)
+    } else {
+        None
     };
-    if !user_seed && verify && !write {
-        return Err(ah::format_err!(
+    if user_seed.is_none() && verify && !write {
+        return Err(DisktestError::InvalidArguments.msg(
             "Verify-only mode requires --seed. \
-             Please either provide a --seed, \
-             or enable --verify and --write mode."
+             Please either provide a --seed (or --seed-file, --seed-stdin, \
+             --seed-env, --seed-prompt), or enable --verify and --write mode.",
         ));
     }
+    let (seed, user_seed) = match user_seed {
+        Some(seed) => (seed, true),
+        None => (gen_seed_string(DEFAULT_GEN_SEED_LEN), false),
+    };
+
+    let gen_seed_file = args.get_one::<PathBuf>("gen-seed-file").cloned();
 
     let invert_pattern = args.get_flag("invert-pattern");
 
-    let threads = *args.get_one::<u32>("threads").unwrap_or(&1) as usize;
+    let passes = *args.get_one::<u32>("passes").unwrap();
+    if passes > 1 && !write {
+        return Err(DisktestError::InvalidArguments.msg("--passes requires --write mode."));
+    }
+
+    let mut threads = *args.get_one::<u32>("threads").unwrap_or(&1) as usize;
+    if bench && args.value_source("threads") != Some(ValueSource::CommandLine) {
+        // Measure up to all online CPUs.
+        threads = 0;
+    }
 
-    Ok(Args {
+    let mut a = Args {
         device,
         write,
         verify,
+        keep_going,
+        skip_read_errors,
+        hexdump,
+        dump_dir,
+        report,
+        checkpoint,
+        resume,
+        metadata,
+        bench,
+        bench_target,
+        bench_algorithms,
         seek,
         max_bytes,
         algorithm,
+        layout,
+        compress_ratio,
+        dedup_ratio,
+        key_schedule,
         seed,
+        keys: OnceLock::new(),
+        seed_id: OnceLock::new(),
         user_seed,
+        gen_seed_file,
         invert_pattern,
+        passes,
         threads,
         quiet,
-    })
+    };
+
+    // Configure verify-only mode from the recorded run metadata.
+    if let (Some(path), false, true) = (a.metadata.clone(), a.write, a.verify) {
+        let given = |name: &str| args.value_source(name) == Some(ValueSource::CommandLine);
+        RunMetadata::load(&path)?.apply(&path, &mut a, given)?;
+    }
+
+    Ok(a)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::disktest::Disktest;
+    use disktest::exit_code;
 
     #[test]
     fn test_parse_args() {
-        assert!(parse_args(vec!["disktest", "--does-not-exist"]).is_err());
+        let e = parse_args(vec!["disktest", "--does-not-exist"])
+            .err()
+            .unwrap();
+        assert_eq!(exit_code(&e), DisktestError::InvalidArguments.exit_code());
+        let e = parse_args(vec!["disktest", "/dev/foobar"]).err().unwrap();
+        assert_eq!(exit_code(&e), DisktestError::InvalidArguments.exit_code());
 
         let a = parse_args(vec!["disktest", "-Sx", "/dev/foobar"]).unwrap();
         assert_eq!(a.device, PathBuf::from("/dev/foobar"));
         assert!(!a.write);
         assert!(a.verify);
+        assert!(!a.keep_going);
+        assert!(!a.skip_read_errors);
+        assert!(!a.hexdump);
+        assert_eq!(a.dump_dir, None);
+        assert_eq!(a.report, None);
+        assert_eq!(a.checkpoint, None);
+        assert!(!a.resume);
+        assert_eq!(a.metadata, None);
+        assert!(!a.bench);
+        assert_eq!(a.bench_target, None);
+        assert!(a.bench_algorithms.is_empty());
         assert_eq!(a.seek, 0);
         assert_eq!(a.max_bytes, Disktest::UNLIMITED);
         assert_eq!(a.algorithm, DtStreamType::ChaCha20);
-        assert_eq!(a.seed, "x");
+        assert_eq!(a.layout, DtStreamLayout::Interleaved);
+        assert_eq!(a.compress_ratio, DtCompressRatio::NONE);
+        assert_eq!(a.dedup_ratio, DtDedupRatio::NONE);
+        assert_eq!(a.key_schedule, DtKeySchedule::V1);
+        assert_eq!(a.seed.as_str(), "x");
         assert!(a.user_seed);
+        assert_eq!(a.gen_seed_file, None);
         assert!(!a.invert_pattern);
+        assert_eq!(a.passes, 1);
         assert_eq!(a.threads, 1);
         assert_eq!(a.quiet, DisktestQuiet::Normal);
 
@@ -363,6 +842,74 @@ mod tests {
         assert!(!a.write);
         assert!(a.verify);
 
+        let a = parse_args(vec!["disktest", "-Sx", "--keep-going", "/dev/foobar"]).unwrap();
+        assert!(a.keep_going);
+        let a = parse_args(vec!["disktest", "-Sx", "-k", "/dev/foobar"]).unwrap();
+        assert!(a.keep_going);
+
+        let a = parse_args(vec!["disktest", "-Sx", "--skip-read-errors", "/dev/foobar"]).unwrap();
+        assert!(a.skip_read_errors);
+
+        let a = parse_args(vec![
+            "disktest",
+            "-Sx",
+            "--hexdump",
+            "--dump-dir",
+            "/tmp/dump",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert!(a.hexdump);
+        assert_eq!(a.dump_dir, Some(PathBuf::from("/tmp/dump")));
+
+        let a = parse_args(vec!["disktest", "-Sx", "--report", "r.json", "/dev/foobar"]).unwrap();
+        assert_eq!(a.report, Some(PathBuf::from("r.json")));
+
+        let a = parse_args(vec![
+            "disktest",
+            "-Sx",
+            "--checkpoint",
+            "c.json",
+            "--resume",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.checkpoint, Some(PathBuf::from("c.json")));
+        assert!(a.resume);
+        assert!(parse_args(vec!["disktest", "-Sx", "--resume", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "--bench"]).unwrap();
+        assert!(a.bench);
+        assert!(!a.write);
+        assert!(!a.verify);
+        assert_eq!(a.device, PathBuf::new());
+        assert_eq!(a.bench_algorithms.len(), BENCH_ALGORITHMS.len());
+        assert_eq!(a.threads, 0);
+        let a = parse_args(vec![
+            "disktest",
+            "--bench",
+            "--bench-target",
+            "7 GB",
+            "-A",
+            "splitmix",
+            "-j4",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.device, PathBuf::from("/dev/foobar"));
+        assert_eq!(a.bench_target, Some(7_000_000_000));
+        assert_eq!(a.bench_algorithms, vec![DtStreamType::SplitMix]);
+        assert_eq!(a.threads, 4);
+        assert!(parse_args(vec!["disktest", "--bench", "-w"]).is_err());
+        assert!(parse_args(vec![
+            "disktest",
+            "-Sx",
+            "--bench-target",
+            "1",
+            "/dev/foobar"
+        ])
+        .is_err());
+
         let a = parse_args(vec!["disktest", "-w", "--seek", "123", "/dev/foobar"]).unwrap();
         assert_eq!(a.seek, 123);
         let a = parse_args(vec!["disktest", "-w", "-s", "123 MiB", "/dev/foobar"]).unwrap();
@@ -388,14 +935,152 @@ mod tests {
         assert_eq!(a.algorithm, DtStreamType::ChaCha12);
         let a = parse_args(vec!["disktest", "-w", "-A", "crc", "/dev/foobar"]).unwrap();
         assert_eq!(a.algorithm, DtStreamType::Crc);
+        let a = parse_args(vec!["disktest", "-w", "-A", "aes256ctr", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::Aes256Ctr);
+        let a = parse_args(vec!["disktest", "-w", "-A", "SPLITMIX", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::SplitMix);
+        let a = parse_args(vec!["disktest", "-w", "-A", "walking1", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm, DtStreamType::WalkingOnes);
+        let a = parse_args(vec!["disktest", "-w", "-A", "PATTERN:55AA", "/dev/foobar"]).unwrap();
+        assert_eq!(a.algorithm.pattern(), Some(&[0x55, 0xAA][..]));
         assert!(parse_args(vec!["disktest", "-w", "-A", "invalid", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-w", "-A", "PATTERN:5", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec!["disktest", "-w", "--layout", "indexed", "/dev/foobar"]).unwrap();
+        assert_eq!(a.layout, DtStreamLayout::Indexed);
+        assert!(parse_args(vec!["disktest", "-w", "--layout", "foo", "/dev/foobar"]).is_err());
+
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "--key-schedule",
+            "v2",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.key_schedule, DtKeySchedule::V2);
+        assert!(parse_args(vec![
+            "disktest",
+            "-w",
+            "--key-schedule",
+            "V3",
+            "/dev/foobar"
+        ])
+        .is_err());
+
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "--compress-ratio",
+            "2.5",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.compress_ratio.to_string(), "2.50");
+        assert_eq!(a.compress_ratio.as_f64(), 2.5);
+        for ratio in ["0.5", "65", "x"] {
+            assert!(parse_args(vec![
+                "disktest",
+                "-w",
+                "--compress-ratio",
+                ratio,
+                "/dev/foobar"
+            ])
+            .is_err());
+        }
+        assert!(parse_args(vec![
+            "disktest",
+            "-w",
+            "-A",
+            "TAGGED",
+            "--compress-ratio",
+            "2",
+            "/dev/foobar"
+        ])
+        .is_err());
+
+        let a = parse_args(vec!["disktest", "-w", "--dedup-ratio", "4", "/dev/foobar"]).unwrap();
+        assert_eq!(a.dedup_ratio.to_string(), "4.00");
+        for ratio in ["0.9", "65", "x"] {
+            assert!(parse_args(vec![
+                "disktest",
+                "-w",
+                "--dedup-ratio",
+                ratio,
+                "/dev/foobar"
+            ])
+            .is_err());
+        }
+        assert!(parse_args(vec![
+            "disktest",
+            "-w",
+            "-A",
+            "TAGGED",
+            "--dedup-ratio",
+            "2",
+            "/dev/foobar"
+        ])
+        .is_err());
 
         let a = parse_args(vec!["disktest", "-w", "--seed", "mysecret", "/dev/foobar"]).unwrap();
-        assert_eq!(a.seed, "mysecret");
+        assert_eq!(a.seed.as_str(), "mysecret");
         assert!(a.user_seed);
         let a = parse_args(vec!["disktest", "-w", "-S", "mysecret", "/dev/foobar"]).unwrap();
-        assert_eq!(a.seed, "mysecret");
+        assert_eq!(a.seed.as_str(), "mysecret");
+        assert!(a.user_seed);
+
+        let tdir = tempfile::tempdir().unwrap();
+        let seed_path = tdir.path().join("seed");
+        std::fs::write(&seed_path, "filesecret\n").unwrap();
+        let seed_path = seed_path.to_str().unwrap();
+        let a = parse_args(vec!["disktest", "--seed-file", seed_path, "/dev/foobar"]).unwrap();
+        assert_eq!(a.seed.as_str(), "filesecret");
         assert!(a.user_seed);
+        assert!(parse_args(vec![
+            "disktest",
+            "--seed-file",
+            "/nonexistent",
+            "/dev/foobar"
+        ])
+        .is_err());
+        std::env::set_var("DISKTEST_TEST_ARGS_SEED", "envsecret");
+        let a = parse_args(vec![
+            "disktest",
+            "--seed-env",
+            "DISKTEST_TEST_ARGS_SEED",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.seed.as_str(), "envsecret");
+        assert!(a.user_seed);
+        assert!(parse_args(vec![
+            "disktest",
+            "-Sx",
+            "--seed-file",
+            seed_path,
+            "/dev/foobar"
+        ])
+        .is_err());
+        let a = parse_args(vec![
+            "disktest",
+            "-w",
+            "--gen-seed-file",
+            "f",
+            "/dev/foobar",
+        ])
+        .unwrap();
+        assert_eq!(a.gen_seed_file, Some(PathBuf::from("f")));
+        assert!(!a.user_seed);
+        assert!(parse_args(vec![
+            "disktest",
+            "-w",
+            "-Sx",
+            "--gen-seed-file",
+            "f",
+            "/dev/foobar"
+        ])
+        .is_err());
+        tdir.close().unwrap();
 
         let a = parse_args(vec!["disktest", "-w", "--threads", "24", "/dev/foobar"]).unwrap();
         assert_eq!(a.threads, 24);
@@ -414,6 +1099,30 @@ mod tests {
         assert!(a.invert_pattern);
         let a = parse_args(vec!["disktest", "-w", "-i", "/dev/foobar"]).unwrap();
         assert!(a.invert_pattern);
+
+        let a = parse_args(vec!["disktest", "-w", "--passes", "4", "/dev/foobar"]).unwrap();
+        assert_eq!(a.passes, 4);
+        assert!(parse_args(vec!["disktest", "-w", "--passes", "0", "/dev/foobar"]).is_err());
+        assert!(parse_args(vec!["disktest", "-Sx", "--passes", "2", "/dev/foobar"]).is_err());
+    }
+
+    #[test]
+    fn test_stream_fingerprint() {
+        let fp = |args: &[&str]| {
+            let mut v = vec!["disktest", "-Sx"];
+            v.extend_from_slice(args);
+            v.push("/dev/foobar");
+            parse_args(v).unwrap().stream_fingerprint()
+        };
+        assert_eq!(fp(&["-j2"]), fp(&["-j2"]));
+        assert_ne!(fp(&["-j2"]), fp(&["-j3"]));
+        assert_ne!(fp(&["-j2"]), fp(&["-j2", "-i"]));
+        assert_ne!(fp(&["-j2"]), fp(&["-j2", "-A", "CRC"]));
+        // The indexed layout does not depend on the number of threads.
+        assert_eq!(
+            fp(&["-j2", "--layout", "INDEXED"]),
+            fp(&["-j3", "--layout", "INDEXED"])
+        );
     }
 }
 
@@ -0,0 +1,213 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use disktest::{
+    DisktestFile, DisktestQuiet, DtKeySchedule, DtStreamAgg, DtStreamType, DtTagged,
+    DEFAULT_SECTOR_SIZE,
+};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// All algorithms that are measured by default.
+pub const BENCH_ALGORITHMS: [DtStreamType; 14] = [
+    DtStreamType::ChaCha8,
+    DtStreamType::ChaCha12,
+    DtStreamType::ChaCha20,
+    DtStreamType::Crc,
+    DtStreamType::Aes128Ctr,
+    DtStreamType::Aes256Ctr,
+    DtStreamType::SplitMix,
+    DtStreamType::Tagged(DtTagged { run_id: 0, pass: 0 }),
+    DtStreamType::Zeros,
+    DtStreamType::Ones,
+    DtStreamType::Checker55,
+    DtStreamType::CheckerAA,
+    DtStreamType::WalkingOnes,
+    DtStreamType::WalkingZeros,
+];
+
+/// Time to run the generator threads before the measurement starts.
+const WARMUP_DURATION: Duration = Duration::from_millis(200);
+/// Measurement time per algorithm and thread count.
+const MEASURE_DURATION: Duration = Duration::from_secs(1);
+/// Maximum time of the device read probe.
+const PROBE_DURATION: Duration = Duration::from_secs(3);
+/// Read size of the device read probe.
+const PROBE_CHUNK_SIZE: usize = 1024 * 1024 * 4;
+
+/// Format a rate in bytes per second.
+fn format_rate(rate: f64) -> String {
+    format!("{:.2} GB/s", rate / 1e9)
+}
+
+/// Get the thread counts to measure: 1, 2, 4, ... up to max_threads.
+/// max_threads itself is always included.
+fn thread_counts(max_threads: usize) -> Vec<usize> {
+    let mut counts: Vec<usize> = (0..usize::BITS)
+        .map(|shift| 1 << shift)
+        .take_while(|&count| count < max_threads)
+        .collect();
+    counts.push(max_threads.max(1));
+    counts
+}
+
+/// Find the smallest thread count that reaches the target rate.
+/// rates: Pairs of thread count and measured rate, sorted by thread count.
+fn recommend(rates: &[(usize, f64)], target: f64) -> Option<(usize, f64)> {
+    rates.iter().copied().find(|&(_, rate)| rate >= target)
+}
+
+/// Measure the throughput of the generator stream without disk I/O.
+/// Returns the rate in bytes per second.
+fn measure(
+    stype: DtStreamType,
+    nr_threads: usize,
+    warmup: Duration,
+    duration: Duration,
+) -> ah::Result<f64> {
+    let mut agg = DtStreamAgg::new(
+        stype,
+        b"disktest benchmark".to_vec(),
+        false,
+        nr_threads,
+        DisktestQuiet::NoWarn,
+    );
+    agg.set_key_schedule(DtKeySchedule::V2);
+    agg.activate(0, DEFAULT_SECTOR_SIZE)?;
+
+    // Don't measure the key derivation and the thread startup.
+    let begin = Instant::now();
+    while begin.elapsed() < warmup {
+        agg.wait_chunk()?;
+    }
+
+    let begin = Instant::now();
+    let mut bytes = 0;
+    while begin.elapsed() < duration {
+        bytes += agg.wait_chunk()?.get_data().len() as u64;
+    }
+    Ok(bytes as f64 / begin.elapsed().as_secs_f64())
+}
+
+/// Measure the sequential read rate of the device.
+/// Returns the rate in bytes per second.
+fn probe_device(path: &Path) -> ah::Result<f64> {
+    DisktestFile::open(path, true, false)?.measure_read_rate(PROBE_CHUNK_SIZE, PROBE_DURATION)
+}
+
+/// Run the generator throughput benchmark.
+/// algorithms: The algorithms to measure.
+/// max_threads: The maximum number of threads to measure.
+/// target: The target rate in bytes per second.
+/// device: Probe the read rate of this device, if no target is given.
+pub fn bench(
+    algorithms: &[DtStreamType],
+    max_threads: usize,
+    target: Option<u64>,
+    device: Option<&Path>,
+) -> ah::Result<()> {
+    let target = match (target, device) {
+        (Some(target), _) => Some((target as f64, "--bench-target".to_string())),
+        (None, Some(device)) => {
+            println!("Probing the read rate of {}...", device.display());
+            let rate = probe_device(device)?;
+            Some((rate, format!("read rate of {}", device.display())))
+        }
+        (None, None) => None,
+    };
+
+    println!("Generator throughput without disk I/O:");
+    let mut results = vec![];
+    for &stype in algorithms {
+        print!("    {:<12}", stype.to_string());
+        let mut rates = vec![];
+        for nr_threads in thread_counts(max_threads) {
+            let rate = measure(stype, nr_threads, WARMUP_DURATION, MEASURE_DURATION)?;
+            print!("  -j{}: {}", nr_threads, format_rate(rate));
+            std::io::Write::flush(&mut std::io::stdout())?;
+            rates.push((nr_threads, rate));
+        }
+        println!();
+        results.push((stype, rates));
+    }
+
+    if let Some((target, source)) = target {
+        println!(
+            "\nRecommended number of threads for {} ({}):",
+            format_rate(target),
+            source
+        );
+        for (stype, rates) in &results {
+            match recommend(rates, target) {
+                Some((nr_threads, rate)) => println!(
+                    "    {:<12}  -j{} ({})",
+                    stype.to_string(),
+                    nr_threads,
+                    format_rate(rate)
+                ),
+                None => println!(
+                    "    {:<12}  None. The generator is slower than the target (CPU bound).",
+                    stype.to_string()
+                ),
+            }
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_counts() {
+        assert_eq!(thread_counts(1), vec![1]);
+        assert_eq!(thread_counts(2), vec![1, 2]);
+        assert_eq!(thread_counts(6), vec![1, 2, 4, 6]);
+        assert_eq!(thread_counts(8), vec![1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn test_recommend() {
+        let rates = [(1, 1e9), (2, 2e9), (4, 3.5e9)];
+        assert_eq!(recommend(&rates, 0.5e9), Some((1, 1e9)));
+        assert_eq!(recommend(&rates, 1.5e9), Some((2, 2e9)));
+        assert_eq!(recommend(&rates, 3.5e9), Some((4, 3.5e9)));
+        assert_eq!(recommend(&rates, 4e9), None);
+    }
+
+    #[test]
+    fn test_measure() {
+        let rate = measure(
+            DtStreamType::SplitMix,
+            2,
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        assert!(rate > 0.0);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,342 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::error::DisktestError;
+use crate::stream::{DtCompressRatio, DtDedupRatio, DtKeySchedule, DtStreamLayout};
+use crate::stream_aggregator::DtStreamType;
+use anyhow as ah;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Version of the checkpoint file format.
+const CHECKPOINT_FORMAT_VERSION: u64 = 1;
+
+/// The phase of a checkpointed run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckpointPhase {
+    /// Nothing has been done, yet.
+    Start,
+    Write,
+    Verify,
+    /// The run has completed.
+    Done,
+}
+
+impl CheckpointPhase {
+    fn name(&self) -> &'static str {
+        match self {
+            CheckpointPhase::Start => "start",
+            CheckpointPhase::Write => "write",
+            CheckpointPhase::Verify => "verify",
+            CheckpointPhase::Done => "done",
+        }
+    }
+
+    fn from_name(name: &str) -> ah::Result<CheckpointPhase> {
+        match name {
+            "start" => Ok(CheckpointPhase::Start),
+            "write" => Ok(CheckpointPhase::Write),
+            "verify" => Ok(CheckpointPhase::Verify),
+            "done" => Ok(CheckpointPhase::Done),
+            _ => Err(ah::format_err!("Invalid checkpoint phase '{}'.", name)),
+        }
+    }
+}
+
+/// Progress of a run that can be resumed later.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Path of the checkpoint file.
+    pub path: PathBuf,
+    pub device: PathBuf,
+    pub algorithm: DtStreamType,
+    pub layout: DtStreamLayout,
+    pub compress_ratio: DtCompressRatio,
+    pub dedup_ratio: DtDedupRatio,
+    pub key_schedule: DtKeySchedule,
+    /// The actual number of threads.
+    pub threads: usize,
+    pub seed_fingerprint: String,
+    /// Fingerprint of the seed and the stream parameters.
+    pub stream_fingerprint: String,
+    pub invert_pattern: bool,
+    pub write: bool,
+    pub verify: bool,
+    /// The --seek argument of the run.
+    pub seek: u64,
+    /// The --bytes argument of the run.
+    pub max_bytes: u64,
+    /// The --passes argument of the run.
+    pub passes: u32,
+    /// The current pass, starting at 1.
+    pub pass: u32,
+    /// The current phase.
+    pub phase: CheckpointPhase,
+    /// Absolute byte offset where the current phase started.
+    pub phase_start: u64,
+    /// Absolute byte offset up to which the current phase has completed.
+    /// In the write phase all data up to here has been synced to disk.
+    pub offset: u64,
+}
+
+impl Checkpoint {
+    /// Get the number of bytes that remain to be processed in the current phase.
+    pub fn remaining_bytes(&self) -> u64 {
+        if self.max_bytes == u64::MAX {
+            u64::MAX
+        } else {
+            self.max_bytes
+                .saturating_sub(self.offset.saturating_sub(self.phase_start))
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "format_version": CHECKPOINT_FORMAT_VERSION,
+            "device": self.device.to_string_lossy(),
+            "algorithm": self.algorithm.to_string(),
+            "layout": self.layout.to_string(),
+            "compress_ratio": self.compress_ratio.to_string(),
+            "dedup_ratio": self.dedup_ratio.to_string(),
+            "key_schedule": self.key_schedule.to_string(),
+            "threads": self.threads,
+            "seed_fingerprint": self.seed_fingerprint,
+            "stream_fingerprint": self.stream_fingerprint,
+            "invert_pattern": self.invert_pattern,
+            "write": self.write,
+            "verify": self.verify,
+            "seek": self.seek,
+            "max_bytes": self.max_bytes,
+            "passes": self.passes,
+            "pass": self.pass,
+            "phase": self.phase.name(),
+            "phase_start": self.phase_start,
+            "offset": self.offset,
+        })
+    }
+
+    fn from_json(path: &Path, json: &Value) -> ah::Result<Checkpoint> {
+        let err = |name| ah::format_err!("Checkpoint field '{}' is missing or invalid.", name);
+        let get_u64 = |name| json[name].as_u64().ok_or_else(|| err(name));
+        let get_bool = |name| json[name].as_bool().ok_or_else(|| err(name));
+        let get_str = |name| json[name].as_str().ok_or_else(|| err(name));
+
+        let version = get_u64("format_version")?;
+        if version != CHECKPOINT_FORMAT_VERSION {
+            return Err(ah::format_err!(
+                "Unsupported checkpoint format version {}.",
+                version
+            ));
+        }
+        let algorithm: DtStreamType = get_str("algorithm")?.parse()?;
+        let layout: DtStreamLayout = get_str("layout")?.parse()?;
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            device: PathBuf::from(get_str("device")?),
+            algorithm,
+            layout,
+            compress_ratio: get_str("compress_ratio")?.parse()?,
+            dedup_ratio: get_str("dedup_ratio")?.parse()?,
+            key_schedule: get_str("key_schedule")?.parse()?,
+            threads: get_u64("threads")? as usize,
+            seed_fingerprint: get_str("seed_fingerprint")?.to_string(),
+            stream_fingerprint: get_str("stream_fingerprint")?.to_string(),
+            invert_pattern: get_bool("invert_pattern")?,
+            write: get_bool("write")?,
+            verify: get_bool("verify")?,
+            seek: get_u64("seek")?,
+            max_bytes: get_u64("max_bytes")?,
+            passes: get_u64("passes")? as u32,
+            pass: get_u64("pass")? as u32,
+            phase: CheckpointPhase::from_name(get_str("phase")?)?,
+            phase_start: get_u64("phase_start")?,
+            offset: get_u64("offset")?,
+        })
+    }
+
+    /// Load a checkpoint file.
+    pub fn load(path: &Path) -> ah::Result<Checkpoint> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ah::format_err!("Failed to read checkpoint {:?}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&text)
+            .map_err(|e| ah::format_err!("Failed to parse checkpoint {:?}: {}", path, e))?;
+        Checkpoint::from_json(path, &json)
+    }
+
+    /// Save the checkpoint file.
+    /// The file is replaced atomically, so that an interruption
+    /// never leaves a truncated checkpoint behind.
+    pub fn save(&self) -> ah::Result<()> {
+        let mut text = serde_json::to_string_pretty(&self.to_json())?;
+        text.push('\n');
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        std::fs::write(&tmp_path, text)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.path))
+            .map_err(|e| ah::format_err!("Failed to write checkpoint {:?}: {}", self.path, e))
+    }
+
+    /// Check whether this checkpoint was created by a run with the parameters of other.
+    pub fn check_resumable(&self, other: &Checkpoint) -> ah::Result<()> {
+        let mut diffs = vec![];
+        if self.algorithm != other.algorithm {
+            diffs.push(format!("--algorithm {}", self.algorithm));
+        }
+        if self.layout != other.layout {
+            diffs.push(format!("--layout {}", self.layout));
+        }
+        if self.compress_ratio != other.compress_ratio {
+            diffs.push(format!("--compress-ratio {}", self.compress_ratio));
+        }
+        if self.dedup_ratio != other.dedup_ratio {
+            diffs.push(format!("--dedup-ratio {}", self.dedup_ratio));
+        }
+        if self.key_schedule != other.key_schedule {
+            diffs.push(format!("--key-schedule {}", self.key_schedule));
+        }
+        // The indexed layout does not depend on the number of threads.
+        if self.threads != other.threads && self.layout == DtStreamLayout::Interleaved {
+            diffs.push(format!("--threads {}", self.threads));
+        }
+        if self.seed_fingerprint != other.seed_fingerprint {
+            diffs.push("--seed of the interrupted run".to_string());
+        }
+        if self.invert_pattern != other.invert_pattern {
+            diffs.push(format!("--invert-pattern {}", self.invert_pattern));
+        }
+        if self.write != other.write || self.verify != other.verify {
+            diffs.push(format!("--write {} --verify {}", self.write, self.verify));
+        }
+        if self.seek != other.seek {
+            diffs.push(format!("--seek {}", self.seek));
+        }
+        if self.max_bytes != other.max_bytes {
+            diffs.push(format!("--bytes {}", self.max_bytes));
+        }
+        if self.passes != other.passes {
+            diffs.push(format!("--passes {}", self.passes));
+        }
+        if diffs.is_empty() {
+            Ok(())
+        } else {
+            Err(DisktestError::InvalidArguments.msg(format!(
+                "The parameters do not match the checkpoint {:?}. Expected: {}",
+                self.path,
+                diffs.join(", ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn mk_checkpoint(path: &Path) -> Checkpoint {
+        Checkpoint {
+            path: path.to_path_buf(),
+            device: PathBuf::from("/dev/foo"),
+            algorithm: DtStreamType::ChaCha12,
+            layout: DtStreamLayout::Interleaved,
+            compress_ratio: DtCompressRatio::NONE,
+            dedup_ratio: DtDedupRatio::NONE,
+            key_schedule: DtKeySchedule::V2,
+            threads: 4,
+            seed_fingerprint: "0123456789abcdef".to_string(),
+            stream_fingerprint: "fedcba9876543210".to_string(),
+            invert_pattern: false,
+            write: true,
+            verify: true,
+            seek: 0,
+            max_bytes: 1000,
+            passes: 2,
+            pass: 1,
+            phase: CheckpointPhase::Write,
+            phase_start: 100,
+            offset: 600,
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("checkpoint.json");
+        let cp = mk_checkpoint(&path);
+        cp.save().unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), cp);
+
+        // The fields of the stream parameters are required.
+        for field in [
+            "layout",
+            "compress_ratio",
+            "dedup_ratio",
+            "key_schedule",
+            "stream_fingerprint",
+        ] {
+            cp.save().unwrap();
+            let mut json: Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            json.as_object_mut().unwrap().remove(field);
+            std::fs::write(&path, json.to_string()).unwrap();
+            assert!(Checkpoint::load(&path).is_err());
+        }
+
+        std::fs::write(&path, "{}").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_remaining() {
+        let mut cp = mk_checkpoint(Path::new("x"));
+        assert_eq!(cp.remaining_bytes(), 500);
+        cp.offset = 2000;
+        assert_eq!(cp.remaining_bytes(), 0);
+        cp.max_bytes = u64::MAX;
+        assert_eq!(cp.remaining_bytes(), u64::MAX);
+    }
+
+    #[test]
+    fn test_resumable() {
+        let cp = mk_checkpoint(Path::new("x"));
+        let mut other = cp.clone();
+        other.phase = CheckpointPhase::Start;
+        other.offset = 0;
+        assert!(cp.check_resumable(&other).is_ok());
+        other.seed_fingerprint = "fedcba9876543210".to_string();
+        other.threads = 2;
+        let e = cp.check_resumable(&other).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "The parameters do not match the checkpoint \"x\". \
+             Expected: --threads 4, --seed of the interrupted run"
+        );
+        let mut cp = cp;
+        cp.layout = DtStreamLayout::Indexed;
+        other.layout = DtStreamLayout::Indexed;
+        other.seed_fingerprint = cp.seed_fingerprint.clone();
+        assert!(cp.check_resumable(&other).is_ok());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
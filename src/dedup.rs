@@ -0,0 +1,132 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::GeneratorSplitMix;
+use crate::kdf::DtKeySet;
+use crate::secret::wipe_value;
+
+/// Deterministic map of the chunks, that repeat the data of an earlier chunk.
+/// Whether a chunk is a duplicate and which chunk it duplicates
+/// only depends on the derived keys of the seed and the chunk index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DedupMap {
+    key: u64,
+    /// A chunk is a duplicate, if its hash is below the threshold.
+    threshold: u64,
+    /// Distance between the chunks that can duplicate each other.
+    stride: u64,
+}
+
+impl DedupMap {
+    /// Maximum deduplication ratio.
+    pub const MAX_RATIO: f64 = 64.0;
+    /// Maximum distance between a duplicate and its source, in strides.
+    pub const WINDOW: u64 = 16;
+
+    /// Create a new deduplication map.
+    /// keys: The derived keys of the seed.
+    /// ratio: The target deduplication ratio (written data / unique data).
+    /// stride: Distance between the chunks that can duplicate each other.
+    /// Only chunks with the same index modulo stride can duplicate each other.
+    pub fn new(keys: &DtKeySet, ratio: f64, stride: u64) -> DedupMap {
+        assert!((1.0..=DedupMap::MAX_RATIO).contains(&ratio));
+        assert!(stride > 0);
+
+        // Fraction of the chunks that are duplicates.
+        let fraction = 1.0 - 1.0 / ratio;
+        DedupMap {
+            key: keys.dedup_key(),
+            threshold: (fraction * u64::MAX as f64) as u64,
+            stride,
+        }
+    }
+
+    /// Get the distance between the chunks that can duplicate each other.
+    pub fn stride(&self) -> u64 {
+        self.stride
+    }
+
+    fn hash(&self, index: u64) -> u64 {
+        GeneratorSplitMix::mix(self.key ^ GeneratorSplitMix::mix(index))
+    }
+
+    /// Check whether the chunk repeats the data of an earlier chunk.
+    pub fn is_duplicate(&self, index: u64) -> bool {
+        index >= self.stride && self.hash(index) < self.threshold
+    }
+
+    /// Get the index of the chunk, whose unique data is repeated by the chunk.
+    /// Returns the index itself, if the chunk is not a duplicate.
+    pub fn source(&self, mut index: u64) -> u64 {
+        // Duplicates of duplicates resolve to the chunk with the unique data.
+        while self.is_duplicate(index) {
+            let window = (index / self.stride).min(DedupMap::WINDOW);
+            let distance = 1 + GeneratorSplitMix::mix(self.hash(index)) % window;
+            index -= distance * self.stride;
+        }
+        index
+    }
+}
+
+impl Drop for DedupMap {
+    fn drop(&mut self) {
+        wipe_value(&mut self.key, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::DtKeySchedule;
+
+    #[test]
+    fn test_ratio() {
+        let keys = DtKeySet::derive(&[1, 2, 3], DtKeySchedule::V2, 1);
+        let count = 10000;
+        for (ratio, stride) in [(1.0, 1), (2.0, 1), (4.0, 3)] {
+            let map = DedupMap::new(&keys, ratio, stride);
+            let mut unique = 0;
+            for index in 0..count {
+                let source = map.source(index);
+                assert!(source <= index);
+                assert_eq!(source % stride, index % stride);
+                assert!(!map.is_duplicate(source));
+                if source == index {
+                    unique += 1;
+                }
+            }
+            let actual = count as f64 / unique as f64;
+            assert!((actual - ratio).abs() < ratio * 0.05);
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let a = DedupMap::new(&DtKeySet::derive(&[1, 2, 3], DtKeySchedule::V2, 1), 2.0, 1);
+        let b = DedupMap::new(&DtKeySet::derive(&[1, 2, 4], DtKeySchedule::V2, 1), 2.0, 1);
+        let sources = |map: &DedupMap| (0..100).map(|i| map.source(i)).collect::<Vec<_>>();
+        assert_eq!(sources(&a), sources(&a.clone()));
+        assert_ne!(sources(&a), sources(&b));
+        assert!(!a.is_duplicate(0));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
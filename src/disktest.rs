@@ -19,21 +19,37 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+use crate::checkpoint::{Checkpoint, CheckpointPhase};
+use crate::displacement::{Displacement, DisplacementAnalyzer};
+use crate::dump::{dump_to_dir, hexdump_diff};
+use crate::error::{errno, DisktestError};
+use crate::mismatch::{MismatchMap, UnreadableMap};
 use crate::rawio::{RawIo, RawIoResult, DEFAULT_SECTOR_SIZE};
+use crate::report::{PhaseReport, Throughput};
+use crate::secret::DtSecret;
 use crate::stream_aggregator::{DtStreamAgg, DtStreamAggChunk};
 use crate::util::{prettybytes, Hhmmss};
 use anyhow as ah;
 use std::cmp::min;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::available_parallelism;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+pub use crate::kdf::{DtKeySet, DtSeedId};
+pub use crate::stream::{DtCompressRatio, DtDedupRatio, DtKeySchedule, DtStreamLayout};
 pub use crate::stream_aggregator::DtStreamType;
 
 const LOG_BYTE_THRES: u64 = 1024 * 1024;
 const LOG_SEC_THRES: u64 = 10;
+/// Maximum number of displacement analyses per verification run.
+const MAX_DISPLACEMENT_ANALYSES: usize = 16;
+/// Maximum number of mismatching regions saved to the dump directory per verification run.
+const MAX_DUMPS: usize = 16;
+/// Minimum time between two checkpoint file updates.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DisktestQuiet {
@@ -161,6 +177,30 @@ impl DisktestFile {
     fn get_path(&self) -> &PathBuf {
         &self.path
     }
+
+    /// Measure the sequential read rate from the start of the file.
+    /// chunk_size: The size of a single read.
+    /// duration: The maximum measurement time.
+    /// Returns the rate in bytes per second.
+    pub fn measure_read_rate(&mut self, chunk_size: usize, duration: Duration) -> ah::Result<f64> {
+        self.seek(0)?;
+        let mut buffer = vec![0; chunk_size];
+        let mut bytes = 0;
+        let begin = Instant::now();
+        while begin.elapsed() < duration {
+            match self.read(&mut buffer)? {
+                RawIoResult::Ok(0) => break,
+                RawIoResult::Ok(n) => bytes += n as u64,
+                RawIoResult::Enospc => unreachable!(),
+            }
+        }
+        let elapsed = begin.elapsed().as_secs_f64();
+        self.close()?;
+        if bytes == 0 {
+            return Err(ah::format_err!("Read probe of {:?}: No data.", self.path));
+        }
+        Ok(bytes as f64 / elapsed)
+    }
 }
 
 impl Drop for DisktestFile {
@@ -182,7 +222,26 @@ pub struct Disktest {
     log_count: u64,
     log_time: Instant,
     begin_time: Instant,
+    end_time: Option<Instant>,
+    processed: u64,
+    throughput: Throughput,
+    nr_threads: usize,
     quiet_level: DisktestQuiet,
+    keep_going: bool,
+    skip_read_errors: bool,
+    sector_size: u32,
+    chunk_size: u64,
+    start_offset: u64,
+    mismatches: MismatchMap,
+    unreadable: UnreadableMap,
+    analyzer: Option<DisplacementAnalyzer>,
+    analyses: usize,
+    displacements: Vec<Displacement>,
+    hexdump: bool,
+    dump_dir: Option<PathBuf>,
+    dumps: usize,
+    checkpoint: Option<Checkpoint>,
+    checkpoint_time: Instant,
 }
 
 impl Disktest {
@@ -192,21 +251,13 @@ impl Disktest {
     /// Create a new Disktest instance.
     pub fn new(
         algorithm: DtStreamType,
-        seed: Vec<u8>,
+        seed: impl Into<DtSecret>,
         invert_pattern: bool,
         nr_threads: usize,
         quiet_level: DisktestQuiet,
         abort: Option<Arc<AtomicBool>>,
     ) -> Disktest {
-        let nr_threads = if nr_threads == 0 {
-            if let Ok(cpus) = available_parallelism() {
-                cpus.get()
-            } else {
-                1
-            }
-        } else {
-            nr_threads
-        };
+        let nr_threads = Disktest::resolve_nr_threads(nr_threads);
 
         Disktest {
             stream_agg: DtStreamAgg::new(algorithm, seed, invert_pattern, nr_threads, quiet_level),
@@ -214,10 +265,118 @@ impl Disktest {
             log_count: 0,
             log_time: Instant::now(),
             begin_time: Instant::now(),
+            end_time: None,
+            processed: 0,
+            throughput: Throughput::new(),
+            nr_threads,
             quiet_level,
+            keep_going: false,
+            skip_read_errors: false,
+            sector_size: DEFAULT_SECTOR_SIZE,
+            chunk_size: 0,
+            start_offset: 0,
+            mismatches: MismatchMap::new(),
+            unreadable: UnreadableMap::new(),
+            analyzer: None,
+            analyses: 0,
+            displacements: vec![],
+            hexdump: false,
+            dump_dir: None,
+            dumps: 0,
+            checkpoint: None,
+            checkpoint_time: Instant::now(),
         }
     }
 
+    /// Get the actual number of threads for the --threads argument.
+    /// The special value 0 selects the number of online CPUs.
+    pub fn resolve_nr_threads(nr_threads: usize) -> usize {
+        if nr_threads == 0 {
+            if let Ok(cpus) = available_parallelism() {
+                cpus.get()
+            } else {
+                1
+            }
+        } else {
+            nr_threads
+        }
+    }
+
+    /// Record the progress in a checkpoint file.
+    pub fn set_checkpoint(&mut self, checkpoint: Option<Checkpoint>) {
+        self.checkpoint = checkpoint;
+    }
+
+    /// Take the checkpoint with the recorded progress.
+    pub fn take_checkpoint(&mut self) -> Option<Checkpoint> {
+        self.checkpoint.take()
+    }
+
+    /// Select the layout of the generated stream.
+    pub fn set_layout(&mut self, layout: DtStreamLayout) {
+        self.stream_agg.set_layout(layout);
+    }
+
+    /// Select the target compression ratio of the generated data.
+    pub fn set_compress_ratio(&mut self, compress: DtCompressRatio) {
+        self.stream_agg.set_compress_ratio(compress);
+    }
+
+    /// Select the target deduplication ratio of the generated data.
+    pub fn set_dedup_ratio(&mut self, dedup: DtDedupRatio) {
+        self.stream_agg.set_dedup_ratio(dedup);
+    }
+
+    /// Select the key schedule, that derives the generator keys from the seed.
+    pub fn set_key_schedule(&mut self, key_schedule: DtKeySchedule) {
+        self.stream_agg.set_key_schedule(key_schedule);
+    }
+
+    /// Reuse the generator keys of another instance with the same seed.
+    /// That avoids deriving the keys again in every phase.
+    pub fn set_key_set(&mut self, keys: Arc<DtKeySet>) {
+        self.stream_agg.set_key_set(keys);
+    }
+
+    /// Get the generator keys, if they have already been derived.
+    pub fn get_key_set(&self) -> Option<Arc<DtKeySet>> {
+        self.stream_agg.get_key_set()
+    }
+
+    /// Continue verification after a data mismatch
+    /// and collect all corrupted regions.
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+
+    /// Skip unreadable sectors during verification
+    /// and collect a list of them instead of aborting.
+    pub fn set_skip_read_errors(&mut self, skip_read_errors: bool) {
+        self.skip_read_errors = skip_read_errors;
+    }
+
+    /// Print a hexdump of the expected and the actual data
+    /// around the first mismatch.
+    pub fn set_hexdump(&mut self, hexdump: bool) {
+        self.hexdump = hexdump;
+    }
+
+    /// Save the raw sectors of mismatching regions
+    /// and the expected data to a directory.
+    pub fn set_dump_dir(&mut self, dump_dir: Option<PathBuf>) {
+        self.dump_dir = dump_dir;
+    }
+
+    /// Get the corrupted regions found by the last verification.
+    pub fn get_mismatches(&self) -> &MismatchMap {
+        &self.mismatches
+    }
+
+    /// Get the unreadable sectors found by the last verification.
+    pub fn get_unreadable(&self) -> &UnreadableMap {
+        &self.unreadable
+    }
+
     /// Abort was requested by user?
     fn abort_requested(&self) -> bool {
         if let Some(abort) = &self.abort {
@@ -232,10 +391,20 @@ impl Disktest {
         self.log_count = 0;
         self.log_time = Instant::now();
         self.begin_time = self.log_time;
+        self.end_time = None;
+        self.processed = 0;
+        self.throughput = Throughput::new();
     }
 
     /// Log progress.
     fn log(&mut self, prefix: &str, inc_processed: usize, abs_processed: u64, final_step: bool) {
+        // Collect the statistics for the report.
+        self.processed = abs_processed;
+        self.throughput.add(inc_processed as u64);
+        if final_step {
+            self.end_time = Some(Instant::now());
+        }
+
         // Info logging is enabled?
         if self.quiet_level < DisktestQuiet::NoInfo {
             // Increment byte count.
@@ -252,11 +421,8 @@ impl Disktest {
                 if (expired && self.quiet_level == DisktestQuiet::Normal) || final_step {
                     let dur_elapsed = now - self.begin_time;
                     let sec_elapsed = dur_elapsed.as_secs();
-                    let rate = if sec_elapsed > 0 {
-                        format!(
-                            " @ {}/s",
-                            prettybytes(abs_processed / sec_elapsed, true, false, false)
-                        )
+                    let rate = if let Some(rate) = abs_processed.checked_div(sec_elapsed) {
+                        format!(" @ {}/s", prettybytes(rate, true, false, false))
                     } else {
                         "".to_string()
                     };
@@ -290,6 +456,7 @@ impl Disktest {
         self.log_reset();
 
         let sector_size = file.get_sector_size().unwrap_or(None);
+        self.sector_size = sector_size.unwrap_or(DEFAULT_SECTOR_SIZE);
 
         if self.quiet_level < DisktestQuiet::NoInfo {
             let sector_str = if let Some(sector_size) = sector_size.as_ref() {
@@ -309,25 +476,26 @@ impl Disktest {
             );
         }
 
-        let res = match self
+        // Unaligned raw device I/O would only fail later with a confusing error.
+        if !seek.is_multiple_of(self.sector_size as u64) {
+            return Err(DisktestError::InvalidArguments.msg(format!(
+                "The seek position {} is not a multiple of the sector size {}.",
+                prettybytes(seek, true, true, true),
+                prettybytes(self.sector_size as u64, true, true, true)
+            )));
+        }
+
+        let res = self
             .stream_agg
-            .activate(seek, sector_size.unwrap_or(DEFAULT_SECTOR_SIZE))
-        {
-            Ok(res) => res,
-            Err(e) => return Err(e),
-        };
+            .activate(seek, sector_size.unwrap_or(DEFAULT_SECTOR_SIZE))?;
 
         if let Err(e) = file.seek(res.byte_offset) {
-            return Err(ah::format_err!(
-                "File seek to {} failed: {}",
-                seek,
-                e.to_string()
-            ));
+            return Err(ah::format_err!("File seek to {} failed: {}", seek, e));
         }
 
         if let Some(sector_size) = sector_size.as_ref() {
             if max_bytes < u64::MAX
-                && max_bytes % *sector_size as u64 != 0
+                && !max_bytes.is_multiple_of(*sector_size as u64)
                 && self.quiet_level < DisktestQuiet::NoWarn
             {
                 #[cfg(target_os = "windows")]
@@ -338,9 +506,41 @@ impl Disktest {
             }
         }
 
+        self.chunk_size = res.chunk_size;
+        self.start_offset = res.byte_offset;
         Ok(res.chunk_size)
     }
 
+    /// Start a new phase in the checkpoint, if this is not a resumed phase.
+    fn checkpoint_begin(&mut self, phase: CheckpointPhase) {
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            if checkpoint.phase != phase {
+                checkpoint.phase = phase;
+                checkpoint.phase_start = self.start_offset;
+                checkpoint.offset = self.start_offset;
+            }
+        }
+        self.checkpoint_time = Instant::now();
+    }
+
+    /// Is it time to update the checkpoint file?
+    fn checkpoint_due(&self) -> bool {
+        self.checkpoint.is_some() && self.checkpoint_time.elapsed() >= CHECKPOINT_INTERVAL
+    }
+
+    /// Record the absolute offset up to which the current phase has completed.
+    fn checkpoint_save(&mut self, offset: u64) {
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            checkpoint.offset = offset;
+            if let Err(e) = checkpoint.save() {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: {}", e);
+                }
+            }
+        }
+        self.checkpoint_time = Instant::now();
+    }
+
     /// Finalize and flush writing.
     fn write_finalize(
         &mut self,
@@ -352,8 +552,13 @@ impl Disktest {
             println!("Writing stopped. Syncing...");
         }
         if let Err(e) = file.sync() {
-            return Err(ah::format_err!("Sync failed: {}", e));
+            return Err(DisktestError::WriteError {
+                offset: self.start_offset + bytes_written,
+                errno: errno(&e),
+            }
+            .msg(format!("Sync failed: {:#}", e)));
         }
+        self.checkpoint_save(self.start_offset + bytes_written);
 
         self.log(
             if success { "Done. Wrote " } else { "Wrote " },
@@ -381,11 +586,12 @@ impl Disktest {
         let mut bytes_left = max_bytes;
         let mut bytes_written = 0u64;
 
-        let write_chunk_size = self.init(&mut file, "Writing", seek, max_bytes)?;
+        self.init(&mut file, "Writing", seek, max_bytes)?;
+        self.checkpoint_begin(CheckpointPhase::Write);
         loop {
             // Get the next data chunk.
             let chunk = self.stream_agg.wait_chunk()?;
-            let write_len = min(write_chunk_size, bytes_left) as usize;
+            let write_len = min(chunk.get_data().len() as u64, bytes_left) as usize;
 
             // Write the chunk to disk.
             match file.write(&chunk.get_data()[0..write_len]) {
@@ -396,11 +602,23 @@ impl Disktest {
                         break; // End of device. -> Success.
                     }
                     let _ = self.write_finalize(&mut file, false, bytes_written);
-                    return Err(ah::format_err!("Write error: Out of disk space."));
+                    return Err(DisktestError::OutOfSpace {
+                        offset: self.start_offset + bytes_written,
+                    }
+                    .msg("Write error: Out of disk space."));
                 }
                 Err(e) => {
+                    let offset = self.start_offset + bytes_written;
                     let _ = self.write_finalize(&mut file, false, bytes_written);
-                    return Err(e);
+                    return Err(DisktestError::WriteError {
+                        offset,
+                        errno: errno(&e),
+                    }
+                    .msg(format!(
+                        "Write error at {}: {:#}",
+                        prettybytes(offset, true, true, true),
+                        e
+                    )));
                 }
             }
 
@@ -413,9 +631,23 @@ impl Disktest {
             }
             self.log("Wrote ", write_len, bytes_written, false);
 
+            if self.checkpoint_due() {
+                // Only synced data is recorded as written.
+                if let Err(e) = file.sync() {
+                    let offset = self.start_offset + bytes_written;
+                    let _ = self.write_finalize(&mut file, false, bytes_written);
+                    return Err(DisktestError::WriteError {
+                        offset,
+                        errno: errno(&e),
+                    }
+                    .msg(format!("Sync failed: {:#}", e)));
+                }
+                self.checkpoint_save(self.start_offset + bytes_written);
+            }
+
             if self.abort_requested() {
                 let _ = self.write_finalize(&mut file, false, bytes_written);
-                return Err(ah::format_err!("Aborted by signal!"));
+                return Err(DisktestError::Aborted.msg("Aborted by signal!"));
             }
         }
 
@@ -439,12 +671,161 @@ impl Disktest {
             bytes_read,
             true,
         );
+        self.verify_checkpoint_save(bytes_read);
         if let Err(e) = file.close() {
             return Err(ah::format_err!("Failed to close device: {}", e));
         }
         Ok(())
     }
 
+    /// Record the verified bytes in the checkpoint.
+    /// The checkpoint is not advanced after the first error,
+    /// so that a resumed run finds it again.
+    fn verify_checkpoint_save(&mut self, bytes_read: u64) {
+        if self.checkpoint.is_some() && self.mismatches.is_empty() && self.unreadable.is_empty() {
+            self.checkpoint_save(self.start_offset + bytes_read);
+        }
+    }
+
+    /// Search the expected stream for the origin of the first corrupted sector.
+    /// offset: The absolute byte offset of the buffers.
+    fn analyze_displacement(
+        &mut self,
+        offset: u64,
+        actual: &[u8],
+        expected: &[u8],
+    ) -> Option<Displacement> {
+        if self.analyses >= MAX_DISPLACEMENT_ANALYSES {
+            return None;
+        }
+        let index = actual.iter().zip(expected).position(|(a, e)| a != e)? as u64;
+        let sector_size = self.sector_size as u64;
+        let begin = (offset + index) - ((offset + index) % sector_size);
+        let begin = begin.checked_sub(offset)?;
+        let (begin, end) = (begin as usize, (begin + sector_size) as usize);
+        if end > actual.len() {
+            return None;
+        }
+        self.analyses += 1;
+
+        let analyzer = self.analyzer.get_or_insert_with(|| {
+            DisplacementAnalyzer::new(
+                self.stream_agg.duplicate(),
+                self.sector_size,
+                self.chunk_size,
+            )
+        });
+        match analyzer.analyze(
+            offset + begin as u64,
+            &actual[begin..end],
+            &expected[begin..end],
+        ) {
+            Ok(Some(displacement)) => {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: Displacement: {}.", displacement);
+                }
+                self.displacements.push(displacement.clone());
+                Some(displacement)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: Displacement analysis failed: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Print the hexdump and save the raw data of a mismatching chunk, if requested.
+    /// offset: The absolute byte offset of the buffers.
+    fn dump_mismatch(&mut self, offset: u64, actual: &[u8], expected: &[u8]) {
+        let dumps = self.dumps;
+        self.dumps += 1;
+
+        if self.hexdump && dumps == 0 && self.quiet_level < DisktestQuiet::NoInfo {
+            if let Some(dump) = hexdump_diff(offset, actual, expected) {
+                println!("Hexdump of the first mismatch:");
+                print!("{}", dump);
+                println!();
+            }
+        }
+
+        let Some(dump_dir) = self.dump_dir.as_ref() else {
+            return;
+        };
+        if dumps >= MAX_DUMPS {
+            return;
+        }
+        // Save all sectors between the first and the last mismatching byte.
+        let Some(first) = actual.iter().zip(expected).position(|(a, e)| a != e) else {
+            return;
+        };
+        let last = actual
+            .iter()
+            .zip(expected)
+            .rposition(|(a, e)| a != e)
+            .unwrap();
+        let sector_size = self.sector_size as u64;
+        let begin = (offset + first as u64) / sector_size * sector_size;
+        let begin = (begin.max(offset) - offset) as usize;
+        let end = (offset + last as u64) / sector_size * sector_size + sector_size;
+        let end = ((end - offset) as usize).min(actual.len());
+        match dump_to_dir(
+            dump_dir,
+            offset + begin as u64,
+            &actual[begin..end],
+            &expected[begin..end],
+        ) {
+            Ok((actual_path, _)) => {
+                if self.quiet_level < DisktestQuiet::NoInfo {
+                    println!(
+                        "Saved {} of mismatching data to {:?}.",
+                        prettybytes((end - begin) as u64, true, true, false),
+                        actual_path
+                    );
+                }
+            }
+            Err(e) => {
+                if self.quiet_level < DisktestQuiet::NoWarn {
+                    eprintln!("WARNING: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Get the report of the last write or verify phase.
+    /// phase: Name of the phase.
+    /// result: The result returned by the phase.
+    pub fn phase_report(&self, phase: &'static str, result: &ah::Result<u64>) -> PhaseReport {
+        let end_time = self.end_time.unwrap_or_else(Instant::now);
+        let error = result
+            .as_ref()
+            .err()
+            .map(|e| (e.downcast_ref::<DisktestError>().cloned(), e.to_string()));
+        PhaseReport {
+            phase,
+            pass: 1,
+            offset: self.start_offset,
+            bytes: self.processed,
+            duration: end_time - self.begin_time,
+            throughput: self.throughput.clone(),
+            sector_size: self.sector_size,
+            threads: self.nr_threads,
+            error,
+            mismatches: self.mismatches.regions().to_vec(),
+            merge_gap: self.mismatches.merge_gap(),
+            bits: self.mismatches.bits().clone(),
+            unreadable: self.unreadable.regions().to_vec(),
+            displacements: self.displacements.clone(),
+        }
+    }
+
+    /// Get the displacements found by the last verification.
+    pub fn get_displacements(&self) -> &[Displacement] {
+        &self.displacements
+    }
+
     /// Handle verification failure.
     fn verify_failed(
         &mut self,
@@ -459,85 +840,312 @@ impl Disktest {
                 eprintln!("{}", e);
             }
         }
+        let offset = self.start_offset + bytes_read;
+        self.dump_mismatch(
+            offset,
+            &buffer[..read_count],
+            &chunk.get_data()[..read_count],
+        );
+        self.mismatches.add_buffers(
+            offset,
+            &buffer[..read_count],
+            &chunk.get_data()[..read_count],
+        );
+        let displacement = self.analyze_displacement(
+            offset,
+            &buffer[..read_count],
+            &chunk.get_data()[..read_count],
+        );
+        self.verify_print_report();
+        let analysis = if let Some(displacement) = displacement {
+            format!(" Analysis: {}.", displacement)
+        } else {
+            "".to_string()
+        };
         for (i, buffer_byte) in buffer.iter().enumerate().take(read_count) {
             if *buffer_byte != chunk.get_data()[i] {
-                let pos = bytes_read + i as u64;
+                let pos = offset + i as u64;
+                let error = DisktestError::Mismatch {
+                    offset: pos,
+                    bad_bytes: self.mismatches.bad_bytes(),
+                };
                 if pos >= 1024 {
-                    return ah::format_err!(
-                        "Data MISMATCH at {}!",
-                        prettybytes(pos, true, true, true)
-                    );
+                    return error.msg(format!(
+                        "Data MISMATCH at {}!{}",
+                        prettybytes(pos, true, true, true),
+                        analysis
+                    ));
                 } else {
-                    return ah::format_err!("Data MISMATCH at byte {}!", pos);
+                    return error.msg(format!("Data MISMATCH at byte {}!{}", pos, analysis));
                 }
             }
         }
         panic!("Internal error: verify_failed() no mismatch.");
     }
 
+    /// Record a verification failure and continue.
+    /// offset: The absolute byte offset of the buffers.
+    fn verify_mismatch_continue(&mut self, offset: u64, actual: &[u8], expected: &[u8]) {
+        let count = self.mismatches.add_buffers(offset, actual, expected);
+        if self.quiet_level < DisktestQuiet::NoWarn {
+            eprintln!(
+                "WARNING: Data MISMATCH: {} bad bytes in chunk at {}. Continuing...",
+                count,
+                prettybytes(offset, true, true, true)
+            );
+        }
+        self.dump_mismatch(offset, actual, expected);
+        self.analyze_displacement(offset, actual, expected);
+    }
+
+    /// Read a piece of the disk and bisect read errors down to single sectors.
+    /// offset: The absolute byte offset of the buffer.
+    /// base: The offset of the buffer within the current chunk.
+    /// skipped: Receives the chunk ranges of unreadable sectors.
+    /// Returns the number of bytes read or skipped.
+    /// This is less than the buffer size at the end of the disk.
+    fn verify_read_bisect(
+        &mut self,
+        file: &mut DisktestFile,
+        offset: u64,
+        buffer: &mut [u8],
+        base: usize,
+        skipped: &mut Vec<Range<usize>>,
+    ) -> ah::Result<usize> {
+        // Try to read the whole piece.
+        file.seek_noflush(offset)?;
+        let mut count = 0;
+        while count < buffer.len() {
+            match file.read(&mut buffer[count..]) {
+                Ok(RawIoResult::Ok(0)) => return Ok(count),
+                Ok(RawIoResult::Ok(n)) => count += n,
+                Ok(_) => unreachable!(),
+                Err(_) => break,
+            }
+        }
+        if count == buffer.len() {
+            return Ok(count);
+        }
+
+        if self.abort_requested() {
+            return Err(DisktestError::Aborted.msg("Aborted by signal!"));
+        }
+
+        let sector_size = self.sector_size as u64;
+        let len = buffer.len() as u64;
+        if (offset % sector_size) + len <= sector_size {
+            // This is a single unreadable sector. Skip it.
+            if self.quiet_level < DisktestQuiet::NoWarn {
+                eprintln!(
+                    "WARNING: Unreadable sector {} at {}. Skipping...",
+                    offset / sector_size,
+                    prettybytes(offset, true, true, true)
+                );
+            }
+            self.unreadable.add(offset, len);
+            skipped.push(base..base + buffer.len());
+            return Ok(buffer.len());
+        }
+
+        // Split the piece at a sector boundary and retry both halves.
+        let split = offset + len / 2;
+        let split =
+            (split - (split % sector_size)).max(offset - (offset % sector_size) + sector_size);
+        let mid = (split - offset) as usize;
+        let first = self.verify_read_bisect(file, offset, &mut buffer[..mid], base, skipped)?;
+        if first < mid {
+            return Ok(first);
+        }
+        let second =
+            self.verify_read_bisect(file, split, &mut buffer[mid..], base + mid, skipped)?;
+        Ok(mid + second)
+    }
+
+    /// Print the report of all collected corrupted regions and unreadable sectors, if any.
+    fn verify_print_report(&self) {
+        if self.quiet_level >= DisktestQuiet::NoInfo {
+            return;
+        }
+        if !self.mismatches.is_empty() {
+            self.mismatches.print_report(self.sector_size);
+            if !self.displacements.is_empty() {
+                println!("Displacement analysis:");
+                for displacement in &self.displacements {
+                    println!("    {}", displacement);
+                }
+                println!();
+            }
+        }
+        self.unreadable.print_report(self.sector_size);
+    }
+
+    /// Report all collected corrupted regions and unreadable sectors
+    /// and return the final error.
+    /// read_errno: The error number of the first read error, if known.
+    fn verify_final_error(&self, read_errno: Option<i32>) -> ah::Error {
+        self.verify_print_report();
+        let mut msgs = vec![];
+        if let Some(first) = self.mismatches.regions().first() {
+            msgs.push(format!(
+                "Data MISMATCH: {} bad bytes in {} corrupted regions, starting at {}!",
+                self.mismatches.bad_bytes(),
+                self.mismatches.regions().len(),
+                prettybytes(first.offset, true, true, true)
+            ));
+        }
+        if let Some(first) = self.unreadable.regions().first() {
+            msgs.push(format!(
+                "Read errors: {} unreadable sectors, starting at {}!",
+                self.unreadable.bad_sectors(self.sector_size),
+                prettybytes(first.offset, true, true, true)
+            ));
+        }
+        let msg = msgs.join(" ");
+        if let Some(first) = self.mismatches.regions().first() {
+            DisktestError::Mismatch {
+                offset: first.offset,
+                bad_bytes: self.mismatches.bad_bytes(),
+            }
+            .msg(msg)
+        } else {
+            DisktestError::ReadError {
+                offset: self.unreadable.regions().first().map_or(0, |r| r.offset),
+                errno: read_errno,
+            }
+            .msg(msg)
+        }
+    }
+
     /// Run disktest in verify mode.
     pub fn verify(&mut self, file: DisktestFile, seek: u64, max_bytes: u64) -> ah::Result<u64> {
         let mut file = file;
         let mut bytes_left = max_bytes;
         let mut bytes_read = 0u64;
 
+        self.mismatches.clear();
+        self.unreadable.clear();
+        self.analyzer = None;
+        self.analyses = 0;
+        self.displacements.clear();
+        self.dumps = 0;
         let readbuf_len = self.init(&mut file, "Verifying", seek, max_bytes)? as usize;
+        self.checkpoint_begin(CheckpointPhase::Verify);
         let mut buffer = vec![0; readbuf_len];
         let mut read_count = 0;
-        let mut read_len = min(readbuf_len as u64, bytes_left) as usize;
+        // The first chunk starts at the seek offset within the chunk.
+        let first_len = readbuf_len - (self.start_offset % self.chunk_size) as usize;
+        let mut read_len = min(first_len as u64, bytes_left) as usize;
+        let mut skipped = vec![];
+        let mut read_errno = None;
 
         loop {
             // Read the next chunk from disk.
-            match file.read(&mut buffer[read_count..read_count + (read_len - read_count)]) {
-                Ok(RawIoResult::Ok(n)) => {
-                    read_count += n;
-
-                    // Check if the read buffer is full, or if we are the the end of the disk.
-                    assert!(read_count <= read_len);
-                    if read_count == read_len || (read_count > 0 && n == 0) {
-                        // Calculate and compare the read buffer to the pseudo random sequence.
-                        let chunk = self.stream_agg.wait_chunk()?;
-                        if buffer[..read_count] != chunk.get_data()[..read_count] {
-                            return Err(self.verify_failed(
-                                &mut file, read_count, bytes_read, &buffer, &chunk,
-                            ));
+            let offset = self.start_offset + bytes_read + read_count as u64;
+            let (n, eof) = match file.read(&mut buffer[read_count..read_len]) {
+                Ok(RawIoResult::Ok(n)) => (n, n == 0),
+                Ok(_) => unreachable!(),
+                Err(e) => {
+                    if !self.skip_read_errors {
+                        let _ = self.verify_finalize(&mut file, false, bytes_read);
+                        self.verify_print_report();
+                        return Err(DisktestError::ReadError {
+                            offset,
+                            errno: errno(&e),
                         }
-
-                        // Account for the read bytes.
-                        bytes_read += read_count as u64;
-                        bytes_left -= read_count as u64;
-                        if bytes_left == 0 {
-                            self.verify_finalize(&mut file, true, bytes_read)?;
-                            break;
+                        .msg(format!(
+                            "Read error at {}: {:#}",
+                            prettybytes(offset, true, true, true),
+                            e
+                        )));
+                    }
+                    if read_errno.is_none() {
+                        read_errno = errno(&e);
+                    }
+                    if self.quiet_level < DisktestQuiet::NoWarn {
+                        eprintln!(
+                            "WARNING: Read error at {}: {:#}. Locating unreadable sectors...",
+                            prettybytes(offset, true, true, true),
+                            e
+                        );
+                    }
+                    let want = read_len - read_count;
+                    let res = self.verify_read_bisect(
+                        &mut file,
+                        offset,
+                        &mut buffer[read_count..read_len],
+                        read_count,
+                        &mut skipped,
+                    );
+                    let res = res.and_then(|n| file.seek_noflush(offset + n as u64).map(|_| n));
+                    match res {
+                        Ok(n) => (n, n < want),
+                        Err(e) => {
+                            let _ = self.verify_finalize(&mut file, false, bytes_read);
+                            self.verify_print_report();
+                            return Err(e);
                         }
-                        self.log("Verified ", read_count, bytes_read, false);
-                        read_count = 0;
-                        read_len = min(readbuf_len as u64, bytes_left) as usize;
                     }
+                }
+            };
+            read_count += n;
 
-                    // End of the disk?
-                    if n == 0 {
-                        self.verify_finalize(&mut file, true, bytes_read)?;
-                        break;
+            // Check if the read buffer is full, or if we are the the end of the disk.
+            assert!(read_count <= read_len);
+            if read_count == read_len || (read_count > 0 && eof) {
+                // Calculate and compare the read buffer to the pseudo random sequence.
+                let chunk = self.stream_agg.wait_chunk()?;
+
+                // Unreadable sectors have been skipped. Don't compare them.
+                for range in skipped.drain(..) {
+                    buffer[range.clone()].copy_from_slice(&chunk.get_data()[range]);
+                }
+
+                if buffer[..read_count] != chunk.get_data()[..read_count] {
+                    if !self.keep_going {
+                        return Err(
+                            self.verify_failed(&mut file, read_count, bytes_read, &buffer, &chunk)
+                        );
                     }
+                    self.verify_mismatch_continue(
+                        self.start_offset + bytes_read,
+                        &buffer[..read_count],
+                        &chunk.get_data()[..read_count],
+                    );
                 }
-                Ok(_) => unreachable!(),
-                Err(e) => {
-                    let _ = self.verify_finalize(&mut file, false, bytes_read);
-                    return Err(ah::format_err!(
-                        "Read error at {}: {}",
-                        prettybytes(bytes_read, true, true, true),
-                        e
-                    ));
+
+                // Account for the read bytes.
+                bytes_read += read_count as u64;
+                bytes_left -= read_count as u64;
+                if bytes_left == 0 {
+                    let success = self.mismatches.is_empty() && self.unreadable.is_empty();
+                    self.verify_finalize(&mut file, success, bytes_read)?;
+                    break;
                 }
-            };
+                self.log("Verified ", read_count, bytes_read, false);
+                if self.checkpoint_due() {
+                    self.verify_checkpoint_save(bytes_read);
+                }
+                read_count = 0;
+                read_len = min(readbuf_len as u64, bytes_left) as usize;
+            }
+
+            // End of the disk?
+            if eof {
+                let success = self.mismatches.is_empty() && self.unreadable.is_empty();
+                self.verify_finalize(&mut file, success, bytes_read)?;
+                break;
+            }
 
             if self.abort_requested() {
                 let _ = self.verify_finalize(&mut file, false, bytes_read);
-                return Err(ah::format_err!("Aborted by signal!"));
+                self.verify_print_report();
+                return Err(DisktestError::Aborted.msg("Aborted by signal!"));
             }
         }
 
+        if !self.mismatches.is_empty() || !self.unreadable.is_empty() {
+            return Err(self.verify_final_error(read_errno));
+        }
         Ok(bytes_read)
     }
 }
@@ -546,8 +1154,9 @@ impl Disktest {
 mod tests {
     use super::*;
     use crate::generator::{GeneratorChaCha12, GeneratorChaCha20, GeneratorChaCha8, GeneratorCrc};
+    use crate::stream::DtTagged;
     use std::fs::OpenOptions;
-    use std::io::{Seek, SeekFrom, Write};
+    use std::io::{Read, Seek, SeekFrom, Write};
     use std::path::PathBuf;
     use tempfile::tempdir;
 
@@ -662,10 +1271,154 @@ mod tests {
                 file.seek(SeekFrom::Start(10)).unwrap();
                 writeln!(&file, "X").unwrap();
             }
-            match dt.verify(mk_file(serial, false), 0, nr_bytes) {
+            let dump_dir = tdir_path.join("dump");
+            dt.set_hexdump(true);
+            dt.set_dump_dir(Some(dump_dir.clone()));
+            let res = dt.verify(mk_file(serial, false), 0, nr_bytes);
+            dt.set_hexdump(false);
+            dt.set_dump_dir(None);
+            let actual = std::fs::read(dump_dir.join("0000000000000000-actual.bin")).unwrap();
+            let expected = std::fs::read(dump_dir.join("0000000000000000-expected.bin")).unwrap();
+            assert_eq!(actual.len(), 512);
+            assert_eq!(&actual[10..12], b"X\n");
+            assert_ne!(actual, expected);
+            assert_eq!(actual[..10], expected[..10]);
+            match res {
                 Ok(_) => panic!("Verify of modified data did not fail!"),
-                Err(e) => assert_eq!(e.to_string(), "Data MISMATCH at byte 10!"),
+                Err(e) => {
+                    assert_eq!(e.to_string(), "Data MISMATCH at byte 10!");
+                    assert_eq!(
+                        e.downcast_ref::<DisktestError>(),
+                        Some(&DisktestError::Mismatch {
+                            offset: 10,
+                            bad_bytes: 2
+                        })
+                    );
+                }
+            }
+            serial += 1;
+        }
+
+        // Modify the written data at multiple places and collect all mismatches.
+        {
+            let nr_bytes = (base_size * chunk_factor * nr_threads * 2) as u64;
+            assert_eq!(
+                dt.write(mk_file(serial, true), 0, nr_bytes).unwrap(),
+                nr_bytes
+            );
+            let bad_offsets = [10, 4000, nr_bytes - 100];
+            {
+                let path = mk_filepath(serial);
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap();
+                for offset in bad_offsets {
+                    let mut byte = [0u8; 1];
+                    file.seek(SeekFrom::Start(offset)).unwrap();
+                    file.read_exact(&mut byte).unwrap();
+                    file.seek(SeekFrom::Start(offset)).unwrap();
+                    file.write_all(&[byte[0] ^ 0x01]).unwrap();
+                }
+            }
+            dt.set_keep_going(true);
+            assert!(dt.verify(mk_file(serial, false), 0, u64::MAX).is_err());
+            dt.set_keep_going(false);
+            let regions = dt.get_mismatches().regions();
+            assert_eq!(regions.len(), bad_offsets.len());
+            for (region, offset) in regions.iter().zip(bad_offsets.iter()) {
+                assert_eq!(region.offset, *offset);
+                assert_eq!(region.len, 1);
+                assert_eq!(region.bits.flipped(), 1);
+                assert_eq!(region.bits.positions, [1, 0, 0, 0, 0, 0, 0, 0]);
             }
+            assert_eq!(dt.get_mismatches().bad_sectors(512), 3);
+            serial += 1;
+        }
+
+        // Skip unreadable sectors and continue verification.
+        {
+            let nr_bytes = (base_size * chunk_factor * nr_threads * 2) as u64;
+            assert_eq!(
+                dt.write(mk_file(serial, true), 0, nr_bytes).unwrap(),
+                nr_bytes
+            );
+            let bad = vec![1000..1001, 4096..4096 + 1024, nr_bytes - 10..nr_bytes - 9];
+            let mk_bad_file = || {
+                let path = mk_filepath(serial);
+                let io = RawIo::new_bad_ranges(&path, bad.clone()).unwrap();
+                DisktestFile {
+                    path,
+                    read: true,
+                    write: false,
+                    io: Some(io),
+                    drop_offset: 0,
+                    drop_count: 0,
+                    quiet_level: DisktestQuiet::Normal,
+                }
+            };
+            let e = dt.verify(mk_bad_file(), 0, u64::MAX).unwrap_err();
+            assert!(e.to_string().starts_with("Read error at 0 bytes"));
+            assert_eq!(
+                e.downcast_ref::<DisktestError>(),
+                Some(&DisktestError::ReadError {
+                    offset: 0,
+                    errno: Some(libc::EIO)
+                })
+            );
+            dt.set_skip_read_errors(true);
+            let e = dt.verify(mk_bad_file(), 0, u64::MAX).unwrap_err();
+            dt.set_skip_read_errors(false);
+            assert_eq!(
+                e.to_string(),
+                "Read errors: 4 unreadable sectors, starting at 512 bytes!"
+            );
+            assert_eq!(
+                e.downcast_ref::<DisktestError>(),
+                Some(&DisktestError::ReadError {
+                    offset: 512,
+                    errno: Some(libc::EIO)
+                })
+            );
+            assert!(dt.get_mismatches().is_empty());
+            let sectors: Vec<(u64, u64)> = dt
+                .get_unreadable()
+                .regions()
+                .iter()
+                .map(|r| (r.offset / 512, r.len / 512))
+                .collect();
+            assert_eq!(sectors, vec![(1, 1), (8, 2), (nr_bytes / 512 - 1, 1)]);
+            serial += 1;
+        }
+
+        // Overwrite a sector with data from a lower address and find its origin.
+        {
+            let nr_bytes = 1024 * 1024 * 2;
+            assert_eq!(
+                dt.write(mk_file(serial, true), 0, nr_bytes).unwrap(),
+                nr_bytes
+            );
+            {
+                let path = mk_filepath(serial);
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap();
+                let mut sector = [0u8; 512];
+                file.read_exact(&mut sector).unwrap();
+                file.seek(SeekFrom::Start(1024 * 1024)).unwrap();
+                file.write_all(&sector).unwrap();
+            }
+            let e = dt.verify(mk_file(serial, false), 0, nr_bytes).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "Data MISMATCH at 1.0 MiB (1.0 MB, 1048576 bytes)! \
+                 Analysis: data at 1.0 MiB is really the data written at 0 bytes \
+                 (aliasing, real capacity \u{2248} 1.0 MiB)."
+            );
+            assert_eq!(dt.get_displacements().len(), 1);
             serial += 1;
         }
 
@@ -678,8 +1431,13 @@ mod tests {
             );
             for offset in (0..nr_bytes).step_by(base_size * chunk_factor / 2) {
                 let bytes_verified = dt.verify(mk_file(serial, false), offset, u64::MAX).unwrap();
-                assert!(bytes_verified > 0 && bytes_verified <= nr_bytes);
+                assert_eq!(bytes_verified, nr_bytes - offset);
             }
+            let offset = (base_size * chunk_factor + 1024 * 1024) as u64;
+            assert_eq!(
+                dt.verify(mk_file(serial, false), offset, 4096).unwrap(),
+                4096
+            );
             serial += 1;
         }
 
@@ -699,12 +1457,127 @@ mod tests {
                 dt.verify(mk_file(serial, false), 0, u64::MAX).unwrap(),
                 nr_bytes + offset
             );
+            serial += 1;
+        }
+
+        // Check write with a seek offset that is not a multiple of the chunk size.
+        {
+            let nr_bytes = (base_size * chunk_factor * nr_threads * 2) as u64;
+            assert_eq!(
+                dt.write(mk_file(serial, true), 0, nr_bytes).unwrap(),
+                nr_bytes
+            );
+            let offset = 1024 * 1024;
+            assert_eq!(
+                dt.write(mk_file(serial, false), offset, 4096).unwrap(),
+                4096
+            );
+            assert_eq!(
+                dt.verify(mk_file(serial, false), 0, u64::MAX).unwrap(),
+                nr_bytes
+            );
+
+            // A seek offset that is not a multiple of the sector size is refused.
+            for res in [
+                dt.write(mk_file(serial, false), offset + 7, 4096),
+                dt.verify(mk_file(serial, false), offset + 7, 4096),
+            ] {
+                assert_eq!(
+                    res.unwrap_err().downcast_ref::<DisktestError>(),
+                    Some(&DisktestError::InvalidArguments)
+                );
+            }
             //serial += 1;
         }
 
         tdir.close().unwrap();
     }
 
+    #[test]
+    fn test_checkpoint() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("tmp.img");
+        let cp_path = tdir.path().join("checkpoint.json");
+        let mk_file = |create| {
+            let io = RawIo::new(&path, create, true, true).unwrap();
+            DisktestFile {
+                path: path.clone(),
+                read: true,
+                write: true,
+                io: Some(io),
+                drop_offset: 0,
+                drop_count: 0,
+                quiet_level: DisktestQuiet::Normal,
+            }
+        };
+        let seed = vec![1, 2, 3];
+        let chunk_size = (GeneratorCrc::BASE_SIZE * GeneratorCrc::DEFAULT_CHUNK_FACTOR) as u64;
+        let nr_bytes = chunk_size * 4;
+        let checkpoint = Checkpoint {
+            path: cp_path.clone(),
+            device: path.clone(),
+            algorithm: DtStreamType::Crc,
+            layout: DtStreamLayout::Interleaved,
+            compress_ratio: DtCompressRatio::NONE,
+            dedup_ratio: DtDedupRatio::NONE,
+            key_schedule: DtKeySchedule::V1,
+            threads: 2,
+            seed_fingerprint: "x".to_string(),
+            stream_fingerprint: "y".to_string(),
+            invert_pattern: false,
+            write: true,
+            verify: true,
+            seek: 0,
+            max_bytes: nr_bytes,
+            passes: 1,
+            pass: 1,
+            phase: CheckpointPhase::Start,
+            phase_start: 0,
+            offset: 0,
+        };
+
+        // Interrupt the write after the first chunk.
+        let abort = Arc::new(AtomicBool::new(true));
+        let mut dt = Disktest::new(
+            DtStreamType::Crc,
+            seed.clone(),
+            false,
+            2,
+            DisktestQuiet::Normal,
+            Some(Arc::clone(&abort)),
+        );
+        dt.set_checkpoint(Some(checkpoint));
+        let e = dt.write(mk_file(true), 0, nr_bytes).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<DisktestError>(),
+            Some(&DisktestError::Aborted)
+        );
+        let cp = Checkpoint::load(&cp_path).unwrap();
+        assert_eq!(cp.phase, CheckpointPhase::Write);
+        assert_eq!(cp.offset, chunk_size);
+        assert_eq!(cp.remaining_bytes(), nr_bytes - chunk_size);
+
+        // Resume the write and verify everything.
+        abort.store(false, Ordering::Relaxed);
+        dt.set_checkpoint(Some(cp.clone()));
+        assert_eq!(
+            dt.write(mk_file(false), cp.offset, cp.remaining_bytes())
+                .unwrap(),
+            nr_bytes - chunk_size
+        );
+        let cp = Checkpoint::load(&cp_path).unwrap();
+        assert_eq!(cp.phase, CheckpointPhase::Write);
+        assert_eq!(cp.phase_start, 0);
+        assert_eq!(cp.offset, nr_bytes);
+        assert_eq!(dt.verify(mk_file(false), 0, nr_bytes).unwrap(), nr_bytes);
+        let cp = Checkpoint::load(&cp_path).unwrap();
+        assert_eq!(cp.phase, CheckpointPhase::Verify);
+        assert_eq!(cp.offset, nr_bytes);
+        assert_eq!(cp.remaining_bytes(), 0);
+
+        tdir.close().unwrap();
+    }
+
     #[test]
     fn test_chacha8() {
         run_test(
@@ -740,6 +1613,100 @@ mod tests {
             GeneratorCrc::DEFAULT_CHUNK_FACTOR,
         );
     }
+
+    #[test]
+    fn test_tagged() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("tmp.img");
+        let mk_file = |create| DisktestFile {
+            path: path.clone(),
+            read: true,
+            write: true,
+            io: Some(RawIo::new(&path, create, true, true).unwrap()),
+            drop_offset: 0,
+            drop_count: 0,
+            quiet_level: DisktestQuiet::Normal,
+        };
+        let mk_dt = |run_id| {
+            Disktest::new(
+                DtStreamType::Tagged(DtTagged { run_id, pass: 1 }),
+                vec![42],
+                false,
+                2,
+                DisktestQuiet::Normal,
+                None,
+            )
+        };
+
+        // Misdirected write.
+        let nr_bytes = 1024 * 1024 * 8;
+        let mut dt = mk_dt(1);
+        assert_eq!(dt.write(mk_file(true), 0, nr_bytes).unwrap(), nr_bytes);
+        let mut data = std::fs::read(&path).unwrap();
+        let offset = 1024 * 1024 * 7;
+        data.copy_within(1024..1536, offset);
+        std::fs::write(&path, &data).unwrap();
+        let e = dt.verify(mk_file(false), 0, nr_bytes).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Data MISMATCH at 7.0 MiB (7.3 MB, 7340048 bytes)! \
+             Analysis: data at 7.0 MiB holds data meant for LBA 2 (misdirected write)."
+        );
+
+        // Data of another run.
+        let mut dt = mk_dt(2);
+        let e = dt.verify(mk_file(false), 0, nr_bytes).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Data MISMATCH at byte 8! \
+             Analysis: data at 0 bytes holds stale data meant for LBA 0 from an older run \
+             (run ID 0000000000000001, pass 1)."
+        );
+
+        tdir.close().unwrap();
+    }
+
+    #[test]
+    fn test_patterns() {
+        let tdir = tempdir().unwrap();
+        for (serial, name) in ["WALKING0", "PATTERN:010203"].iter().enumerate() {
+            let algorithm: DtStreamType = name.parse().unwrap();
+            let pattern = algorithm.pattern().unwrap().to_vec();
+            let mut dt = Disktest::new(algorithm, vec![42], false, 3, DisktestQuiet::Normal, None);
+            let path = tdir.path().join(format!("tmp-{}.img", serial));
+            let mk_file = |create| DisktestFile {
+                path: path.clone(),
+                read: true,
+                write: true,
+                io: Some(RawIo::new(&path, create, true, true).unwrap()),
+                drop_offset: 0,
+                drop_count: 0,
+                quiet_level: DisktestQuiet::Normal,
+            };
+
+            // The data on disk only depends on the absolute offset.
+            let nr_bytes = 1024 * 1024 * 20;
+            assert_eq!(dt.write(mk_file(true), 0, nr_bytes).unwrap(), nr_bytes);
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(data.len() as u64, nr_bytes);
+            assert!(data.iter().zip(pattern.iter().cycle()).all(|(a, b)| a == b));
+            assert_eq!(dt.verify(mk_file(false), 0, u64::MAX).unwrap(), nr_bytes);
+
+            // Corrupt a single bit.
+            let mut data = data;
+            data[nr_bytes as usize - 100] ^= 0x10;
+            std::fs::write(&path, &data).unwrap();
+            let e = dt.verify(mk_file(false), 0, u64::MAX).unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<DisktestError>(),
+                Some(&DisktestError::Mismatch {
+                    offset: nr_bytes - 100,
+                    bad_bytes: 1
+                })
+            );
+        }
+        tdir.close().unwrap();
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
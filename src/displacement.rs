@@ -0,0 +1,467 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::dedup::DedupMap;
+use crate::stream_aggregator::DtStreamAgg;
+use crate::tag::SectorTag;
+use crate::util::prettybytes;
+use anyhow as ah;
+use std::collections::BTreeMap;
+
+/// Smallest capacity that is considered for address aliasing.
+const MIN_ALIAS_CAPACITY: u64 = 1024 * 1024;
+/// Maximum number of sectors that data may be shifted by.
+const MAX_SHIFT_SECTORS: u64 = 8;
+
+/// Where corrupted data actually came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisplacementSource {
+    /// The data was written to a lower address,
+    /// because the device wraps addresses around at its real capacity.
+    Aliased { from: u64, capacity: u64 },
+    /// The data was written to a nearby address.
+    Shifted { from: u64, sectors: i64 },
+    /// The data reads as a constant erase pattern.
+    Erased(u8),
+    /// Deduplicating storage returned the data of another chunk.
+    /// expected is the chunk that the data should have been a duplicate of.
+    WrongDuplicate { from: u64, expected: Option<u64> },
+    /// The tag says that the data was meant for another address.
+    Misdirected { lba: u64 },
+    /// The tag says that the data was written by another pass of this run.
+    OtherPass { lba: u64, pass: u32 },
+    /// The tag says that the data was written by another run.
+    OtherRun { lba: u64, run_id: u64, pass: u32 },
+}
+
+/// Result of a displacement analysis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Displacement {
+    /// Absolute byte offset of the analyzed sector.
+    pub offset: u64,
+    /// The source of the data found at offset.
+    pub source: DisplacementSource,
+}
+
+impl std::fmt::Display for Displacement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let at = prettybytes(self.offset, true, false, false);
+        match &self.source {
+            DisplacementSource::Aliased { from, capacity } => write!(
+                f,
+                "data at {} is really the data written at {} \
+                 (aliasing, real capacity \u{2248} {})",
+                at,
+                prettybytes(*from, true, false, false),
+                prettybytes(*capacity, true, false, false)
+            ),
+            DisplacementSource::Shifted { from, sectors } => write!(
+                f,
+                "data at {} is really the data written at {} \
+                 (shifted by {} sectors)",
+                at,
+                prettybytes(*from, true, false, false),
+                sectors
+            ),
+            DisplacementSource::WrongDuplicate { from, expected } => write!(
+                f,
+                "data at {} is really the data written at {} \
+                 (wrong deduplicated block, {})",
+                at,
+                prettybytes(*from, true, false, false),
+                match expected {
+                    Some(expected) => format!(
+                        "expected a duplicate of the chunk at {}",
+                        prettybytes(*expected, true, false, false)
+                    ),
+                    None => "expected unique data".to_string(),
+                }
+            ),
+            DisplacementSource::Erased(value) => write!(
+                f,
+                "data at {} reads as all 0x{:02X} (unwritten or erased)",
+                at, value
+            ),
+            DisplacementSource::Misdirected { lba } => write!(
+                f,
+                "data at {} holds data meant for LBA {} (misdirected write)",
+                at, lba
+            ),
+            DisplacementSource::OtherPass { lba, pass } => write!(
+                f,
+                "data at {} holds stale data meant for LBA {} from pass {} of this run",
+                at, lba, pass
+            ),
+            DisplacementSource::OtherRun { lba, run_id, pass } => write!(
+                f,
+                "data at {} holds stale data meant for LBA {} from an older run \
+                 (run ID {:016X}, pass {})",
+                at, lba, run_id, pass
+            ),
+        }
+    }
+}
+
+/// Get the candidate source offsets for the data found at offset.
+fn candidates(offset: u64, sector_size: u64) -> Vec<(u64, DisplacementSource)> {
+    let mut cands = vec![];
+
+    // Power-of-two address wrap around.
+    // Multiple capacities can map to the same source.
+    // The biggest one is the real capacity.
+    let mut aliases = BTreeMap::new();
+    let mut capacity = MIN_ALIAS_CAPACITY;
+    while capacity <= offset {
+        aliases.insert(offset % capacity, capacity);
+        capacity <<= 1;
+    }
+    for (from, capacity) in aliases {
+        cands.push((from, DisplacementSource::Aliased { from, capacity }));
+    }
+
+    // Shifted by a couple of sectors.
+    for n in 1..=MAX_SHIFT_SECTORS {
+        let distance = n * sector_size;
+        if let Some(from) = offset.checked_sub(distance) {
+            cands.push((
+                from,
+                DisplacementSource::Shifted {
+                    from,
+                    sectors: n as i64,
+                },
+            ));
+        }
+        if let Some(from) = offset.checked_add(distance) {
+            cands.push((
+                from,
+                DisplacementSource::Shifted {
+                    from,
+                    sectors: -(n as i64),
+                },
+            ));
+        }
+    }
+
+    cands
+}
+
+/// Compare the tag of the actual data to the tag of the expected data.
+/// Returns None, if the stream is not tagged, the actual tag is corrupted
+/// or the tags are equal.
+fn tag_source(actual: &[u8], expected: &[u8]) -> Option<DisplacementSource> {
+    let (expected_tag, expected_inverted) = SectorTag::decode(expected)?;
+    let (tag, inverted) = SectorTag::decode(actual)?;
+    let lba = tag.lba;
+    if tag.run_id != expected_tag.run_id {
+        Some(DisplacementSource::OtherRun {
+            lba,
+            run_id: tag.run_id,
+            pass: tag.pass,
+        })
+    } else if tag.pass != expected_tag.pass || inverted != expected_inverted {
+        Some(DisplacementSource::OtherPass {
+            lba,
+            pass: tag.pass,
+        })
+    } else if tag.lba != expected_tag.lba {
+        Some(DisplacementSource::Misdirected { lba })
+    } else {
+        None
+    }
+}
+
+/// Analyzer that searches the expected data stream for misplaced data.
+pub struct DisplacementAnalyzer {
+    agg: DtStreamAgg,
+    sector_size: u32,
+    chunk_size: u64,
+}
+
+impl DisplacementAnalyzer {
+    /// Create a new analyzer.
+    /// agg: An inactive stream aggregator with the same parameters as the verified stream.
+    /// chunk_size: The chunk size of the stream aggregator.
+    pub fn new(agg: DtStreamAgg, sector_size: u32, chunk_size: u64) -> DisplacementAnalyzer {
+        DisplacementAnalyzer {
+            agg,
+            sector_size,
+            chunk_size,
+        }
+    }
+
+    /// Analyze where the data of one corrupted sector came from.
+    /// offset: The sector aligned absolute byte offset of the data.
+    /// actual: The sector data read from the disk.
+    /// expected: The sector data that should have been read.
+    pub fn analyze(
+        &mut self,
+        offset: u64,
+        actual: &[u8],
+        expected: &[u8],
+    ) -> ah::Result<Option<Displacement>> {
+        let sector_size = self.sector_size as u64;
+        debug_assert_eq!(offset % sector_size, 0);
+        debug_assert_eq!(actual.len() as u64, sector_size);
+        debug_assert_eq!(expected.len() as u64, sector_size);
+
+        // Check for erased data.
+        for value in [0x00, 0xFF] {
+            if actual.iter().all(|x| *x == value) {
+                return Ok(Some(Displacement {
+                    offset,
+                    source: DisplacementSource::Erased(value),
+                }));
+            }
+        }
+
+        // Tagged data tells where it belongs to.
+        if let Some(source) = tag_source(actual, expected) {
+            return Ok(Some(Displacement { offset, source }));
+        }
+
+        // Group the candidates by their chunk,
+        // so that each chunk is only generated once.
+        let mut chunks: BTreeMap<u64, Vec<(u64, DisplacementSource)>> = BTreeMap::new();
+
+        // Deduplicating storage may return the data of another chunk,
+        // that the chunk could have been a duplicate of.
+        // A match of these is more specific than the address candidates.
+        if let Some(map) = self.agg.get_dedup_map() {
+            let index = offset / self.chunk_size;
+            let source = map.source(index);
+            let expected = (source != index).then_some(source * self.chunk_size);
+            for distance in 1..=(index / map.stride()).min(DedupMap::WINDOW) {
+                let chunk_offset = (index - distance * map.stride()) * self.chunk_size;
+                let from = chunk_offset + offset % self.chunk_size;
+                chunks
+                    .entry(chunk_offset)
+                    .or_default()
+                    .push((from, DisplacementSource::WrongDuplicate { from, expected }));
+            }
+        }
+
+        for (from, source) in candidates(offset, sector_size) {
+            let chunk_offset = from - (from % self.chunk_size);
+            chunks.entry(chunk_offset).or_default().push((from, source));
+        }
+
+        for (chunk_offset, cands) in chunks {
+            self.agg.activate(chunk_offset, self.sector_size)?;
+            let chunk = self.agg.wait_chunk()?;
+            let data = chunk.get_data();
+            for (from, source) in cands {
+                let begin = (from - chunk_offset) as usize;
+                let end = begin + actual.len();
+                if end <= data.len() && &data[begin..end] == actual {
+                    return Ok(Some(Displacement { offset, source }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disktest::DisktestQuiet;
+    use crate::stream_aggregator::DtStreamType;
+    use crate::tag::TAG_BLOCK_SIZE;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn test_candidates() {
+        let cands = candidates(16 * GIB, 512);
+        assert!(cands.contains(&(
+            0,
+            DisplacementSource::Aliased {
+                from: 0,
+                capacity: 16 * GIB
+            }
+        )));
+        let cands = candidates(24 * GIB, 512);
+        assert!(cands.contains(&(
+            8 * GIB,
+            DisplacementSource::Aliased {
+                from: 8 * GIB,
+                capacity: 16 * GIB
+            }
+        )));
+        assert!(cands.contains(&(
+            0,
+            DisplacementSource::Aliased {
+                from: 0,
+                capacity: 8 * GIB
+            }
+        )));
+        assert!(cands.contains(&(
+            24 * GIB - 1024,
+            DisplacementSource::Shifted {
+                from: 24 * GIB - 1024,
+                sectors: 2
+            }
+        )));
+        let cands = candidates(0, 512);
+        assert_eq!(cands.len(), MAX_SHIFT_SECTORS as usize);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let sector_size = 512;
+        let new_agg = || {
+            DtStreamAgg::new(
+                DtStreamType::Crc,
+                vec![1, 2, 3],
+                false,
+                2,
+                DisktestQuiet::Normal,
+            )
+        };
+
+        // Get the reference stream.
+        let mut agg = new_agg();
+        let chunk_size = agg.activate(0, sector_size).unwrap().chunk_size;
+        let chunk0 = agg.wait_chunk().unwrap().get_data().to_vec();
+        let chunk1 = agg.wait_chunk().unwrap().get_data().to_vec();
+        let mut stream = chunk0;
+        stream.extend_from_slice(&chunk1);
+
+        let mut ana = DisplacementAnalyzer::new(new_agg(), sector_size, chunk_size);
+
+        // Aliasing from 0 MiB to 1 MiB.
+        let offset = MIN_ALIAS_CAPACITY;
+        let expected = &stream[offset as usize..offset as usize + 512];
+        let res = ana
+            .analyze(offset, &stream[0..512], expected)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            res.source,
+            DisplacementSource::Aliased {
+                from: 0,
+                capacity: MIN_ALIAS_CAPACITY
+            }
+        );
+
+        // Shifted across a chunk boundary.
+        let offset = chunk_size + 1024;
+        let res = ana
+            .analyze(
+                offset,
+                &stream[(offset - 1536) as usize..(offset - 1024) as usize],
+                &stream[offset as usize..offset as usize + 512],
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            res.source,
+            DisplacementSource::Shifted {
+                from: offset - 1536,
+                sectors: 3
+            }
+        );
+
+        // Erased.
+        let expected = &stream[512..1024];
+        let res = ana.analyze(512, &[0xFF; 512], expected).unwrap().unwrap();
+        assert_eq!(res.source, DisplacementSource::Erased(0xFF));
+
+        // Unknown data.
+        assert_eq!(ana.analyze(512, &[0x42; 512], expected).unwrap(), None);
+    }
+
+    #[test]
+    fn test_analyze_dedup() {
+        let sector_size = 512;
+        let new_agg = || {
+            let mut agg = DtStreamAgg::new(
+                DtStreamType::SplitMix,
+                vec![1, 2, 3],
+                false,
+                1,
+                DisktestQuiet::Normal,
+            );
+            agg.set_dedup_ratio("2".parse().unwrap());
+            agg
+        };
+
+        let mut agg = new_agg();
+        let map = agg.get_dedup_map().unwrap();
+        let chunk_size = agg.activate(0, sector_size).unwrap().chunk_size;
+        let chunks: Vec<Vec<u8>> = (0..8)
+            .map(|_| agg.wait_chunk().unwrap().get_data().to_vec())
+            .collect();
+
+        // A duplicate chunk returns the data of another unique chunk.
+        let index = (1..8).find(|&i| map.is_duplicate(i)).unwrap();
+        let source = map.source(index);
+        let wrong = (0..index).find(|&i| map.source(i) != source).unwrap();
+        let mut ana = DisplacementAnalyzer::new(new_agg(), sector_size, chunk_size);
+        let res = ana
+            .analyze(
+                index * chunk_size + 1024,
+                &chunks[wrong as usize][1024..1536],
+                &chunks[index as usize][1024..1536],
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            res.source,
+            DisplacementSource::WrongDuplicate {
+                from: wrong * chunk_size + 1024,
+                expected: Some(source * chunk_size),
+            }
+        );
+    }
+
+    #[test]
+    fn test_tag_source() {
+        let tag = |run_id, lba, pass| {
+            let mut block = vec![0x42; TAG_BLOCK_SIZE];
+            SectorTag { run_id, lba, pass }.encode(&mut block);
+            block
+        };
+        let expected = tag(1, 10, 1);
+        assert_eq!(tag_source(&expected, &expected), None);
+        assert_eq!(tag_source(&[0x42; TAG_BLOCK_SIZE], &expected), None);
+        assert_eq!(tag_source(&expected, &[0x42; TAG_BLOCK_SIZE]), None);
+        assert_eq!(
+            tag_source(&tag(1, 12, 1), &expected),
+            Some(DisplacementSource::Misdirected { lba: 12 })
+        );
+        assert_eq!(
+            tag_source(&tag(1, 10, 2), &expected),
+            Some(DisplacementSource::OtherPass { lba: 10, pass: 2 })
+        );
+        assert_eq!(
+            tag_source(&tag(2, 10, 1), &expected),
+            Some(DisplacementSource::OtherRun {
+                lba: 10,
+                run_id: 2,
+                pass: 1
+            })
+        );
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,170 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Number of bytes per hexdump line.
+const HEXDUMP_LINE_LEN: usize = 16;
+/// Number of hexdump lines before and after the first mismatching byte.
+const HEXDUMP_CONTEXT_LINES: usize = 4;
+
+/// Format one half of a hexdump line.
+/// pad: Number of empty cells before the data.
+/// Bytes that differ from the other side are marked with '^^' in the marker line.
+fn hexdump_half(line: &mut String, marks: &mut String, pad: usize, data: &[u8], other: &[u8]) {
+    for i in 0..HEXDUMP_LINE_LEN {
+        if i >= pad && i - pad < data.len() {
+            let _ = write!(line, "{:02X} ", data[i - pad]);
+            if data[i - pad] != other[i - pad] {
+                marks.push_str("^^ ");
+            } else {
+                marks.push_str("   ");
+            }
+        } else {
+            line.push_str("   ");
+            marks.push_str("   ");
+        }
+    }
+}
+
+/// Create a side-by-side hexdump of the expected and the actual data
+/// around the first mismatching byte.
+/// offset: The absolute byte offset of the buffers.
+/// Returns None, if the buffers are equal.
+pub fn hexdump_diff(offset: u64, actual: &[u8], expected: &[u8]) -> Option<String> {
+    let len = actual.len().min(expected.len());
+    let index = actual.iter().zip(expected).position(|(a, e)| a != e)?;
+
+    // Align the lines to the absolute offset.
+    let line_len = HEXDUMP_LINE_LEN as u64;
+    let first_line = (offset + index as u64) / line_len;
+    let begin =
+        (first_line.saturating_sub(HEXDUMP_CONTEXT_LINES as u64) * line_len).max(offset) - offset;
+    let end = ((first_line + HEXDUMP_CONTEXT_LINES as u64 + 1) * line_len - offset).min(len as u64);
+
+    let mut dump = format!("{:<16}  {:<48} {}\n", "Offset", "Expected", "Actual");
+    let mut pos = begin as usize;
+    while pos < end as usize {
+        let abs = offset + pos as u64;
+        let next = (((abs / line_len) + 1) * line_len - offset).min(end) as usize;
+        let pad = (abs % line_len) as usize;
+
+        let mut line = format!("{:016X}  ", abs);
+        let mut marks = " ".repeat(line.len());
+        hexdump_half(
+            &mut line,
+            &mut marks,
+            pad,
+            &expected[pos..next],
+            &actual[pos..next],
+        );
+        line.push(' ');
+        marks.push(' ');
+        hexdump_half(
+            &mut line,
+            &mut marks,
+            pad,
+            &actual[pos..next],
+            &expected[pos..next],
+        );
+        dump.push_str(line.trim_end());
+        dump.push('\n');
+        if !marks.trim().is_empty() {
+            dump.push_str(marks.trim_end());
+            dump.push('\n');
+        }
+        pos = next;
+    }
+    Some(dump)
+}
+
+/// Save the actual and the expected data of a mismatching region to a directory.
+/// offset: The absolute byte offset of the buffers.
+/// Returns the paths of the actual and the expected data files.
+pub fn dump_to_dir(
+    dir: &Path,
+    offset: u64,
+    actual: &[u8],
+    expected: &[u8],
+) -> ah::Result<(PathBuf, PathBuf)> {
+    fs::create_dir_all(dir)
+        .map_err(|e| ah::format_err!("Failed to create dump directory {:?}: {}", dir, e))?;
+    let actual_path = dir.join(format!("{:016X}-actual.bin", offset));
+    let expected_path = dir.join(format!("{:016X}-expected.bin", offset));
+    fs::write(&actual_path, actual)
+        .map_err(|e| ah::format_err!("Failed to write {:?}: {}", actual_path, e))?;
+    fs::write(&expected_path, expected)
+        .map_err(|e| ah::format_err!("Failed to write {:?}: {}", expected_path, e))?;
+    Ok((actual_path, expected_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hexdump_diff() {
+        let expected: Vec<u8> = (0..=255).collect();
+        let mut actual = expected.clone();
+        assert_eq!(hexdump_diff(0, &actual, &expected), None);
+
+        actual[100] ^= 0xFF;
+        let dump = hexdump_diff(0, &actual, &expected).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 1 + HEXDUMP_CONTEXT_LINES * 2 + 1 + 1);
+        assert!(lines[1].starts_with("0000000000000020  20 21 "));
+        let line = lines[1 + HEXDUMP_CONTEXT_LINES];
+        assert!(line.starts_with("0000000000000060  60 61 62 63 64 65 "));
+        assert!(line.contains("6F  60 61 62 63 9B 65 "));
+        let marks = lines[2 + HEXDUMP_CONTEXT_LINES];
+        assert_eq!(marks.find("^^"), Some(18 + 4 * 3));
+        assert_eq!(marks.rfind("^^"), Some(18 + 16 * 3 + 1 + 4 * 3));
+
+        // Unaligned buffer at the start of the device.
+        let dump = hexdump_diff(8, &actual[8..40], &expected[8..40]);
+        assert_eq!(dump, None);
+        let mut actual = expected[8..40].to_vec();
+        actual[0] = 0;
+        let dump = hexdump_diff(8, &actual, &expected[8..40]).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with(&format!("0000000000000008  {}08 09 ", "   ".repeat(8))));
+        assert_eq!(lines[2].find("^^"), Some(18 + 8 * 3));
+        assert!(lines[3].starts_with("0000000000000010  10 11 "));
+    }
+
+    #[test]
+    fn test_dump_to_dir() {
+        let tdir = tempdir().unwrap();
+        let dir = tdir.path().join("dump");
+        let (a, e) = dump_to_dir(&dir, 0x1000, &[1, 2], &[1, 3]).unwrap();
+        assert_eq!(a, dir.join("0000000000001000-actual.bin"));
+        assert_eq!(fs::read(a).unwrap(), vec![1, 2]);
+        assert_eq!(fs::read(e).unwrap(), vec![1, 3]);
+        tdir.close().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
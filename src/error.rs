@@ -0,0 +1,180 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use anyhow as ah;
+
+/// Process exit code: Any error that is not listed below.
+pub const EXIT_ERROR: i32 = 1;
+/// Process exit code: Invalid command line arguments.
+pub const EXIT_INVALID_ARGUMENTS: i32 = 2;
+/// Process exit code: Data mismatch during verification.
+pub const EXIT_MISMATCH: i32 = 3;
+/// Process exit code: Read error.
+pub const EXIT_READ_ERROR: i32 = 4;
+/// Process exit code: Write error.
+pub const EXIT_WRITE_ERROR: i32 = 5;
+/// Process exit code: Out of disk space before the requested number of bytes was written.
+pub const EXIT_OUT_OF_SPACE: i32 = 6;
+/// Process exit code: Aborted by signal.
+pub const EXIT_ABORTED: i32 = 7;
+/// Process exit code: Random number generator failure.
+pub const EXIT_GENERATOR_FAILURE: i32 = 8;
+
+/// Typed disktest error.
+/// This is carried inside of an anyhow error,
+/// which holds the detailed human readable message as context.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DisktestError {
+    /// Invalid command line arguments.
+    InvalidArguments,
+    /// Verified data does not match the expected data.
+    /// offset: Absolute byte offset of the first mismatching byte.
+    /// bad_bytes: Total number of mismatching bytes in all corrupted regions.
+    /// They are not necessarily contiguous to the offset.
+    Mismatch { offset: u64, bad_bytes: u64 },
+    /// Reading from the device failed.
+    ReadError { offset: u64, errno: Option<i32> },
+    /// Writing to the device failed.
+    WriteError { offset: u64, errno: Option<i32> },
+    /// The device is full before the requested number of bytes was written.
+    OutOfSpace { offset: u64 },
+    /// Aborted by signal.
+    Aborted,
+    /// The random number generator failed.
+    GeneratorFailure,
+}
+
+impl DisktestError {
+    /// Get the process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DisktestError::InvalidArguments => EXIT_INVALID_ARGUMENTS,
+            DisktestError::Mismatch { .. } => EXIT_MISMATCH,
+            DisktestError::ReadError { .. } => EXIT_READ_ERROR,
+            DisktestError::WriteError { .. } => EXIT_WRITE_ERROR,
+            DisktestError::OutOfSpace { .. } => EXIT_OUT_OF_SPACE,
+            DisktestError::Aborted => EXIT_ABORTED,
+            DisktestError::GeneratorFailure => EXIT_GENERATOR_FAILURE,
+        }
+    }
+
+    /// Get the machine readable name of this error.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisktestError::InvalidArguments => "invalid_arguments",
+            DisktestError::Mismatch { .. } => "mismatch",
+            DisktestError::ReadError { .. } => "read_error",
+            DisktestError::WriteError { .. } => "write_error",
+            DisktestError::OutOfSpace { .. } => "out_of_space",
+            DisktestError::Aborted => "aborted",
+            DisktestError::GeneratorFailure => "generator_failure",
+        }
+    }
+
+    /// Convert this into an anyhow error with a human readable message.
+    pub fn msg(self, msg: impl std::fmt::Display + Send + Sync + 'static) -> ah::Error {
+        ah::Error::new(self).context(msg)
+    }
+}
+
+impl std::fmt::Display for DisktestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let errno = |errno: &Option<i32>| {
+            if let Some(errno) = errno {
+                format!(" (errno {})", errno)
+            } else {
+                "".to_string()
+            }
+        };
+        match self {
+            DisktestError::InvalidArguments => write!(f, "Invalid arguments"),
+            DisktestError::Mismatch { offset, bad_bytes } => {
+                write!(
+                    f,
+                    "Data mismatch of {} bad bytes, the first at byte {}",
+                    bad_bytes, offset
+                )
+            }
+            DisktestError::ReadError { offset, errno: e } => {
+                write!(f, "Read error at byte {}{}", offset, errno(e))
+            }
+            DisktestError::WriteError { offset, errno: e } => {
+                write!(f, "Write error at byte {}{}", offset, errno(e))
+            }
+            DisktestError::OutOfSpace { offset } => {
+                write!(f, "Out of disk space at byte {}", offset)
+            }
+            DisktestError::Aborted => write!(f, "Aborted by signal"),
+            DisktestError::GeneratorFailure => write!(f, "Random number generator failure"),
+        }
+    }
+}
+
+impl std::error::Error for DisktestError {}
+
+/// Get the OS error number from an I/O error, if any.
+pub fn errno(e: &ah::Error) -> Option<i32> {
+    e.downcast_ref::<std::io::Error>()
+        .and_then(|e| e.raw_os_error())
+}
+
+/// Get the process exit code for an error.
+pub fn exit_code(e: &ah::Error) -> i32 {
+    if let Some(e) = e.downcast_ref::<DisktestError>() {
+        e.exit_code()
+    } else {
+        EXIT_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let e = DisktestError::Mismatch {
+            offset: 1,
+            bad_bytes: 2,
+        }
+        .msg("foo");
+        assert_eq!(e.to_string(), "foo");
+        assert_eq!(exit_code(&e), EXIT_MISMATCH);
+        assert_eq!(
+            e.downcast_ref::<DisktestError>(),
+            Some(&DisktestError::Mismatch {
+                offset: 1,
+                bad_bytes: 2
+            })
+        );
+        assert_eq!(exit_code(&DisktestError::Aborted.into()), EXIT_ABORTED);
+        assert_eq!(exit_code(&ah::format_err!("bar")), EXIT_ERROR);
+    }
+
+    #[test]
+    fn test_errno() {
+        let e = ah::Error::new(std::io::Error::from_raw_os_error(5)).context("Read error");
+        assert_eq!(errno(&e), Some(5));
+        assert_eq!(errno(&ah::format_err!("foo")), None);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -19,16 +19,32 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+mod aes;
 mod chacha;
+mod compress;
 mod crc;
+mod pattern;
+mod registry;
+mod splitmix;
+mod tagged;
 
 use crate::util::prettybytes;
 use anyhow as ah;
 
+pub use crate::generator::aes::GeneratorAes128Ctr;
+pub use crate::generator::aes::GeneratorAes256Ctr;
 pub use crate::generator::chacha::GeneratorChaCha12;
 pub use crate::generator::chacha::GeneratorChaCha20;
 pub use crate::generator::chacha::GeneratorChaCha8;
+pub use crate::generator::compress::GeneratorCompress;
 pub use crate::generator::crc::GeneratorCrc;
+pub use crate::generator::pattern::GeneratorPattern;
+pub use crate::generator::registry::{
+    custom_generators, find_generator, register_generator, GeneratorFactory, GeneratorParams,
+    PatternFactory, RandomFactory, TaggedFactory,
+};
+pub use crate::generator::splitmix::GeneratorSplitMix;
+pub use crate::generator::tagged::GeneratorTagged;
 
 pub trait NextRandom {
     /// Get the size of the next() output with count = 1, in bytes.
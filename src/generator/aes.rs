@@ -0,0 +1,197 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::NextRandom;
+use crate::secret::wipe_value;
+use crate::util::fold;
+use aes::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use anyhow as ah;
+
+/// Size of the AES-CTR initial counter block.
+const IV_SIZE: usize = 16;
+
+macro_rules! GeneratorAesCtr {
+    ( $Generator:ident,
+      $Aes:ident,
+      $key_size:literal,
+      $testmodule:ident,
+      $testresult0:literal
+    ) => {
+        /// AES-CTR keystream generator.
+        /// The aes crate uses the AES instructions of the CPU, if available.
+        pub struct $Generator {
+            cipher: ctr::Ctr128BE<aes::$Aes>,
+        }
+
+        impl $Generator {
+            /// Size of the algorithm base output data.
+            pub const BASE_SIZE: usize = 1024 * 2;
+            /// Default chunk size multiplicator.
+            pub const DEFAULT_CHUNK_FACTOR: usize = 1024 + 512;
+
+            const KEY_SIZE: usize = $key_size;
+
+            pub fn new(seed: &[u8]) -> $Generator {
+                assert!(!seed.is_empty());
+                let folded_seed = fold(seed, $Generator::KEY_SIZE + IV_SIZE);
+                let (key, iv) = folded_seed.split_at($Generator::KEY_SIZE);
+                $Generator::with_key(key, iv)
+            }
+
+            fn with_key(key: &[u8], iv: &[u8]) -> $Generator {
+                let cipher = ctr::Ctr128BE::<aes::$Aes>::new(key.into(), iv.into());
+                $Generator { cipher }
+            }
+        }
+
+        impl Drop for $Generator {
+            fn drop(&mut self) {
+                // Overwrite the round keys and the counter.
+                let zero = [0u8; $Generator::KEY_SIZE + IV_SIZE];
+                let (key, iv) = zero.split_at($Generator::KEY_SIZE);
+                wipe_value(
+                    &mut self.cipher,
+                    ctr::Ctr128BE::<aes::$Aes>::new(key.into(), iv.into()),
+                );
+            }
+        }
+
+        impl NextRandom for $Generator {
+            fn get_base_size(&self) -> usize {
+                $Generator::BASE_SIZE
+            }
+
+            fn next(&mut self, buf: &mut [u8], count: usize) {
+                debug_assert!(buf.len() == $Generator::BASE_SIZE * count);
+                // The output is the plain keystream.
+                buf.fill(0);
+                self.cipher.apply_keystream(buf);
+            }
+
+            fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+                self.cipher
+                    .try_seek(byte_offset)
+                    .map_err(|e| ah::format_err!("AES-CTR seek: {}", e))
+            }
+        }
+
+        #[cfg(test)]
+        mod $testmodule {
+            use super::*;
+
+            #[test]
+            fn test_cmp_result() {
+                let mut a = $Generator::new(&[1, 2, 3]);
+                fn reduce(acc: u32, (i, x): (usize, &u8)) -> u32 {
+                    acc.rotate_left(i as u32) ^ (*x as u32)
+                }
+                let mut buf = vec![0u8; $Generator::BASE_SIZE * 3];
+                a.next(&mut buf, 3);
+                assert_eq!(buf.iter().enumerate().fold(0, reduce), $testresult0);
+            }
+
+            #[test]
+            fn test_seed_diff() {
+                let mut a = $Generator::new(&[1, 2, 3]);
+                let mut b = $Generator::new(&[1, 2, 3]);
+                let mut c = $Generator::new(&[1, 2, 4]);
+                let mut buf_a = vec![0u8; $Generator::BASE_SIZE];
+                let mut buf_b = vec![0u8; $Generator::BASE_SIZE];
+                let mut buf_c = vec![0u8; $Generator::BASE_SIZE];
+                a.next(&mut buf_a, 1);
+                b.next(&mut buf_b, 1);
+                c.next(&mut buf_c, 1);
+                assert_eq!(buf_a, buf_b);
+                assert_ne!(buf_a, buf_c);
+                a.next(&mut buf_b, 1);
+                assert_ne!(buf_a, buf_b);
+            }
+
+            #[test]
+            fn test_seek() {
+                let mut a = $Generator::new(&[1, 2, 3]);
+                let mut b = $Generator::new(&[1, 2, 3]);
+                let mut adata = vec![0u8; $Generator::BASE_SIZE * 3];
+                a.next(&mut adata, 3);
+                let mut bdata = vec![0u8; $Generator::BASE_SIZE];
+                b.seek($Generator::BASE_SIZE as u64 * 2).unwrap();
+                b.next(&mut bdata, 1);
+                assert_eq!(&adata[$Generator::BASE_SIZE * 2..], &bdata[..]);
+
+                // The seek is exact to the byte.
+                b.seek(17).unwrap();
+                b.next(&mut bdata, 1);
+                assert_eq!(&adata[17..17 + $Generator::BASE_SIZE], &bdata[..]);
+            }
+        }
+    };
+}
+
+GeneratorAesCtr!(GeneratorAes128Ctr, Aes128, 16, tests_aes128ctr, 678210257);
+GeneratorAesCtr!(GeneratorAes256Ctr, Aes256, 32, tests_aes256ctr, 3037188675);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_nist_vector() {
+        // NIST SP 800-38A, F.5.1 CTR-AES128.Encrypt
+        let mut a = GeneratorAes128Ctr::with_key(
+            &unhex("2b7e151628aed2a6abf7158809cf4f3c"),
+            &unhex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"),
+        );
+        let mut buf = vec![0u8; GeneratorAes128Ctr::BASE_SIZE];
+        a.next(&mut buf, 1);
+        let plaintext = unhex("6bc1bee22e409f96e93d7e117393172a");
+        let ciphertext: Vec<u8> = buf[..16]
+            .iter()
+            .zip(&plaintext)
+            .map(|(k, p)| k ^ p)
+            .collect();
+        assert_eq!(ciphertext, unhex("874d6191b620e3261bef6864990db6ce"));
+
+        // NIST SP 800-38A, F.5.5 CTR-AES256.Encrypt, second block
+        let mut a = GeneratorAes256Ctr::with_key(
+            &unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4"),
+            &unhex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"),
+        );
+        a.seek(16).unwrap();
+        let mut buf = vec![0u8; GeneratorAes256Ctr::BASE_SIZE];
+        a.next(&mut buf, 1);
+        let plaintext = unhex("ae2d8a571e03ac9c9eb76fac45af8e51");
+        let ciphertext: Vec<u8> = buf[..16]
+            .iter()
+            .zip(&plaintext)
+            .map(|(k, p)| k ^ p)
+            .collect();
+        assert_eq!(ciphertext, unhex("f443e3ca4d62b59aca84e990cacaf5c5"));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -20,6 +20,7 @@
 //
 
 use crate::generator::NextRandom;
+use crate::secret::{wipe, wipe_value};
 use crate::util::fold;
 use anyhow as ah;
 use rand::prelude::*;
@@ -51,11 +52,19 @@ macro_rules! GeneratorChaCha {
                 folded_seed.copy_from_slice(&fold(seed, 32));
 
                 let rng = $ChaChaRng::from_seed(folded_seed);
+                wipe(&mut folded_seed);
 
                 $Generator { rng }
             }
         }
 
+        impl Drop for $Generator {
+            fn drop(&mut self) {
+                // Overwrite the key and the keystream state.
+                wipe_value(&mut self.rng, $ChaChaRng::from_seed([0; 32]));
+            }
+        }
+
         impl NextRandom for $Generator {
             fn get_base_size(&self) -> usize {
                 $Generator::BASE_SIZE
@@ -0,0 +1,166 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::{GeneratorSplitMix, NextRandom};
+use crate::secret::{wipe, wipe_value};
+use crate::util::fold;
+use anyhow as ah;
+
+/// Wrapper around another generator, that makes its output compressible.
+/// Every block of the output consists of random data of the inner generator
+/// and one run of a single repeated byte.
+/// The position of the run and the repeated byte only depend on the seed
+/// and the offset of the block. That keeps the output reproducible and seekable.
+pub struct GeneratorCompress {
+    inner: Box<dyn NextRandom>,
+    key: u64,
+    /// Length of the low entropy run in every block.
+    run_len: usize,
+    /// Byte offset in the stream of this generator.
+    offset: u64,
+}
+
+impl GeneratorCompress {
+    /// Size of the blocks, that are compressed independently of each other.
+    pub const BLOCK_SIZE: usize = 4096;
+    /// Maximum compression ratio.
+    pub const MAX_RATIO: f64 = 64.0;
+
+    /// Create a new compressible generator.
+    /// ratio: The target compression ratio (uncompressed size / compressed size).
+    pub fn new(inner: Box<dyn NextRandom>, seed: &[u8], ratio: f64) -> GeneratorCompress {
+        assert!(!seed.is_empty());
+        assert!((1.0..=GeneratorCompress::MAX_RATIO).contains(&ratio));
+
+        let random_len = (GeneratorCompress::BLOCK_SIZE as f64 / ratio).ceil() as usize;
+        let mut key = [0u8; 8];
+        key.copy_from_slice(&fold(seed, 8));
+        let gen = GeneratorCompress {
+            inner,
+            key: u64::from_le_bytes(key) ^ 0x636F6D7072657373,
+            run_len: GeneratorCompress::BLOCK_SIZE - random_len,
+            offset: 0,
+        };
+        wipe(&mut key);
+        gen
+    }
+
+    /// Get the position and the byte value of the low entropy run of a block.
+    fn run(&self, block_index: u64) -> (usize, u8) {
+        let hash = GeneratorSplitMix::mix(self.key ^ GeneratorSplitMix::mix(block_index));
+        let positions = (GeneratorCompress::BLOCK_SIZE - self.run_len + 1) as u64;
+        ((hash % positions) as usize, (hash >> 56) as u8)
+    }
+}
+
+impl Drop for GeneratorCompress {
+    fn drop(&mut self) {
+        wipe_value(&mut self.key, 0);
+    }
+}
+
+impl NextRandom for GeneratorCompress {
+    fn get_base_size(&self) -> usize {
+        self.inner.get_base_size()
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        self.inner.next(buf, count);
+
+        let block_size = GeneratorCompress::BLOCK_SIZE as u64;
+        let end = self.offset + buf.len() as u64;
+        let mut block_index = self.offset / block_size;
+        while block_index * block_size < end {
+            let (pos, value) = self.run(block_index);
+            let run_begin = block_index * block_size + pos as u64;
+            let run_end = run_begin + self.run_len as u64;
+            let begin = run_begin.max(self.offset);
+            let stop = run_end.min(end);
+            if begin < stop {
+                buf[(begin - self.offset) as usize..(stop - self.offset) as usize].fill(value);
+            }
+            block_index += 1;
+        }
+        self.offset = end;
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        self.inner.seek(byte_offset)?;
+        self.offset = byte_offset;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::GeneratorChaCha8;
+
+    fn mk_gen(ratio: f64) -> GeneratorCompress {
+        GeneratorCompress::new(
+            Box::new(GeneratorChaCha8::new(&[1, 2, 3])),
+            &[1, 2, 3],
+            ratio,
+        )
+    }
+
+    /// Get the length of the longest run of equal bytes.
+    fn longest_run(data: &[u8]) -> usize {
+        data.chunk_by(|a, b| a == b).map(|r| r.len()).max().unwrap()
+    }
+
+    #[test]
+    fn test_ratio() {
+        let size = GeneratorChaCha8::BASE_SIZE * 4;
+        for (ratio, run_len) in [(1.0, 0), (2.0, 2048), (4.0, 3072), (3.0, 2730)] {
+            let mut a = mk_gen(ratio);
+            let mut buf = vec![0u8; size];
+            a.next(&mut buf, 4);
+            for block in buf.chunks(GeneratorCompress::BLOCK_SIZE) {
+                let longest = longest_run(block);
+                assert!(longest >= run_len && longest < run_len + 8);
+            }
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        let size = GeneratorChaCha8::BASE_SIZE;
+        let mut a = mk_gen(2.5);
+        let mut adata = vec![0u8; size * 3];
+        a.next(&mut adata, 3);
+        let mut b = mk_gen(2.5);
+        let mut bdata = vec![0u8; size];
+        b.seek(size as u64 * 2).unwrap();
+        b.next(&mut bdata, 1);
+        assert_eq!(&adata[size * 2..], &bdata[..]);
+
+        // The runs differ between blocks and seeds.
+        let mut c =
+            GeneratorCompress::new(Box::new(GeneratorChaCha8::new(&[1, 2, 3])), &[1, 2, 4], 2.5);
+        let mut cdata = vec![0u8; size * 3];
+        c.next(&mut cdata, 3);
+        assert_ne!(adata, cdata);
+        assert_ne!(a.run(0), a.run(1));
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
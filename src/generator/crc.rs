@@ -20,6 +20,7 @@
 //
 
 use crate::generator::NextRandom;
+use crate::secret::{wipe, wipe_value};
 use crate::util::fold;
 use anyhow as ah;
 use crc::{crc64, Hasher64};
@@ -55,6 +56,13 @@ impl GeneratorCrc {
     }
 }
 
+impl Drop for GeneratorCrc {
+    fn drop(&mut self) {
+        wipe(&mut self.folded_seed);
+        wipe_value(&mut self.crc, crc64::Digest::new(crc64::ECMA));
+    }
+}
+
 impl NextRandom for GeneratorCrc {
     fn get_base_size(&self) -> usize {
         GeneratorCrc::BASE_SIZE
@@ -91,7 +99,7 @@ impl NextRandom for GeneratorCrc {
     }
 
     fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
-        if byte_offset % GeneratorCrc::BASE_SIZE as u64 != 0 {
+        if !byte_offset.is_multiple_of(GeneratorCrc::BASE_SIZE as u64) {
             return Err(ah::format_err!(
                 "CRC seek: Byte offset is not a \
                  multiple of the base size ({} bytes).",
@@ -0,0 +1,138 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::NextRandom;
+use anyhow as ah;
+
+/// Generator for a deterministic repeating byte pattern.
+/// The seed is not used.
+pub struct GeneratorPattern {
+    /// One base chunk of the pattern, followed by one extra repetition.
+    block: Vec<u8>,
+    pattern_len: usize,
+    base_size: usize,
+    /// Offset into the pattern of the next output byte.
+    phase: usize,
+}
+
+impl GeneratorPattern {
+    /// Base size per pattern byte.
+    /// The base size is a multiple of the pattern length,
+    /// so that every chunk starts at the beginning of the pattern.
+    const BASE_SIZE_PER_BYTE: usize = 4096;
+    /// Approximate number of pattern repetitions in a base chunk times the chunk factor.
+    const CHUNK_REPETITIONS: usize = 768;
+
+    pub const ZEROS: &'static [u8] = &[0x00];
+    pub const ONES: &'static [u8] = &[0xFF];
+    pub const CHECKER_55: &'static [u8] = &[0x55];
+    pub const CHECKER_AA: &'static [u8] = &[0xAA];
+    pub const WALKING_ONES: &'static [u8] = &[0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80];
+    pub const WALKING_ZEROS: &'static [u8] = &[0xFE, 0xFD, 0xFB, 0xF7, 0xEF, 0xDF, 0xBF, 0x7F];
+
+    pub fn new(pattern: &[u8]) -> GeneratorPattern {
+        assert!(!pattern.is_empty());
+
+        let base_size = GeneratorPattern::base_size(pattern.len());
+        let block: Vec<u8> = pattern
+            .iter()
+            .cycle()
+            .take(base_size + pattern.len())
+            .copied()
+            .collect();
+
+        GeneratorPattern {
+            block,
+            pattern_len: pattern.len(),
+            base_size,
+            phase: 0,
+        }
+    }
+
+    /// Size of the generator base output data for a pattern of the given length.
+    pub fn base_size(pattern_len: usize) -> usize {
+        pattern_len * GeneratorPattern::BASE_SIZE_PER_BYTE
+    }
+
+    /// Default chunk size multiplicator for a pattern of the given length.
+    pub fn default_chunk_factor(pattern_len: usize) -> usize {
+        (GeneratorPattern::CHUNK_REPETITIONS / pattern_len).max(1)
+    }
+}
+
+impl NextRandom for GeneratorPattern {
+    fn get_base_size(&self) -> usize {
+        self.base_size
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == self.base_size * count);
+
+        // The base size is a multiple of the pattern length.
+        // Therefore, the phase does not change.
+        let src = &self.block[self.phase..self.phase + self.base_size];
+        for chunk in buf.chunks_exact_mut(self.base_size) {
+            chunk.copy_from_slice(src);
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        self.phase = (byte_offset % self.pattern_len as u64) as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let mut a = GeneratorPattern::new(&[1, 2, 3]);
+        assert_eq!(a.get_base_size(), 3 * 4096);
+        assert_eq!(GeneratorPattern::default_chunk_factor(3), 256);
+        assert_eq!(GeneratorPattern::default_chunk_factor(1000), 1);
+        let mut buf = vec![0u8; a.get_base_size() * 2];
+        a.next(&mut buf, 2);
+        for (i, x) in buf.iter().enumerate() {
+            assert_eq!(*x as usize, i % 3 + 1);
+        }
+
+        let mut a = GeneratorPattern::new(GeneratorPattern::WALKING_ONES);
+        let mut buf = vec![0u8; a.get_base_size()];
+        a.next(&mut buf, 1);
+        assert_eq!(&buf[..10], &[1, 2, 4, 8, 16, 32, 64, 128, 1, 2]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorPattern::new(&[1, 2, 3]);
+        let mut buf = vec![0u8; a.get_base_size()];
+        a.seek(3 * 1000 + 1).unwrap();
+        a.next(&mut buf, 1);
+        assert_eq!(&buf[..4], &[2, 3, 1, 2]);
+        a.seek(a.get_base_size() as u64 * 5).unwrap();
+        a.next(&mut buf, 1);
+        assert_eq!(&buf[..4], &[1, 2, 3, 1]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
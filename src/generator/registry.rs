@@ -0,0 +1,329 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::{
+    GeneratorAes128Ctr, GeneratorAes256Ctr, GeneratorChaCha12, GeneratorChaCha20, GeneratorChaCha8,
+    GeneratorCrc, GeneratorPattern, GeneratorSplitMix, GeneratorTagged, NextRandom,
+};
+use crate::stream::{DtCustom, DtStreamType};
+use anyhow as ah;
+use std::sync::RwLock;
+
+/// Parameters for the construction of the generator of one thread.
+pub struct GeneratorParams<'a> {
+    /// The key of the generator, derived from the seed.
+    pub key: &'a [u8],
+    /// The index of the generator stream in the aggregated stream.
+    pub stream_id: u32,
+    /// The number of interleaved generator streams.
+    pub stream_count: u32,
+    /// The number of base sizes per chunk.
+    pub chunk_factor: usize,
+}
+
+/// Factory, that constructs the generators of one algorithm.
+pub trait GeneratorFactory: Send + Sync {
+    /// The name of the algorithm, that selects it with --algorithm.
+    fn name(&self) -> &str;
+
+    /// Get the size of the next() output with count = 1, in bytes.
+    fn base_size(&self) -> usize;
+
+    /// Get the default chunk size multiplicator.
+    fn default_chunk_factor(&self) -> usize;
+
+    /// Construct the generator of one thread.
+    fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom>;
+}
+
+impl<T: GeneratorFactory + ?Sized> GeneratorFactory for &T {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn base_size(&self) -> usize {
+        (**self).base_size()
+    }
+
+    fn default_chunk_factor(&self) -> usize {
+        (**self).default_chunk_factor()
+    }
+
+    fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom> {
+        (**self).new_generator(params)
+    }
+}
+
+/// Factory of a built-in random number generator.
+pub struct RandomFactory {
+    name: &'static str,
+    base_size: usize,
+    default_chunk_factor: usize,
+    new: fn(&[u8]) -> Box<dyn NextRandom>,
+}
+
+impl RandomFactory {
+    pub const CHACHA8: RandomFactory = RandomFactory {
+        name: "CHACHA8",
+        base_size: GeneratorChaCha8::BASE_SIZE,
+        default_chunk_factor: GeneratorChaCha8::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorChaCha8::new(key)),
+    };
+    pub const CHACHA12: RandomFactory = RandomFactory {
+        name: "CHACHA12",
+        base_size: GeneratorChaCha12::BASE_SIZE,
+        default_chunk_factor: GeneratorChaCha12::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorChaCha12::new(key)),
+    };
+    pub const CHACHA20: RandomFactory = RandomFactory {
+        name: "CHACHA20",
+        base_size: GeneratorChaCha20::BASE_SIZE,
+        default_chunk_factor: GeneratorChaCha20::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorChaCha20::new(key)),
+    };
+    pub const CRC: RandomFactory = RandomFactory {
+        name: "CRC",
+        base_size: GeneratorCrc::BASE_SIZE,
+        default_chunk_factor: GeneratorCrc::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorCrc::new(key)),
+    };
+    pub const AES128CTR: RandomFactory = RandomFactory {
+        name: "AES128CTR",
+        base_size: GeneratorAes128Ctr::BASE_SIZE,
+        default_chunk_factor: GeneratorAes128Ctr::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorAes128Ctr::new(key)),
+    };
+    pub const AES256CTR: RandomFactory = RandomFactory {
+        name: "AES256CTR",
+        base_size: GeneratorAes256Ctr::BASE_SIZE,
+        default_chunk_factor: GeneratorAes256Ctr::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorAes256Ctr::new(key)),
+    };
+    pub const SPLITMIX: RandomFactory = RandomFactory {
+        name: "SPLITMIX",
+        base_size: GeneratorSplitMix::BASE_SIZE,
+        default_chunk_factor: GeneratorSplitMix::DEFAULT_CHUNK_FACTOR,
+        new: |key| Box::new(GeneratorSplitMix::new(key)),
+    };
+}
+
+impl GeneratorFactory for RandomFactory {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn base_size(&self) -> usize {
+        self.base_size
+    }
+
+    fn default_chunk_factor(&self) -> usize {
+        self.default_chunk_factor
+    }
+
+    fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom> {
+        (self.new)(params.key)
+    }
+}
+
+/// Factory of a repeating byte pattern generator.
+pub struct PatternFactory {
+    name: String,
+    pattern: Vec<u8>,
+}
+
+impl PatternFactory {
+    pub fn new(name: &str, pattern: &[u8]) -> PatternFactory {
+        PatternFactory {
+            name: name.to_string(),
+            pattern: pattern.to_vec(),
+        }
+    }
+}
+
+impl GeneratorFactory for PatternFactory {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn base_size(&self) -> usize {
+        GeneratorPattern::base_size(self.pattern.len())
+    }
+
+    fn default_chunk_factor(&self) -> usize {
+        GeneratorPattern::default_chunk_factor(self.pattern.len())
+    }
+
+    fn new_generator(&self, _params: &GeneratorParams) -> Box<dyn NextRandom> {
+        Box::new(GeneratorPattern::new(&self.pattern))
+    }
+}
+
+/// Factory of the tagged generator.
+pub struct TaggedFactory {
+    pub run_id: u64,
+    pub pass: u32,
+}
+
+impl GeneratorFactory for TaggedFactory {
+    fn name(&self) -> &str {
+        "TAGGED"
+    }
+
+    fn base_size(&self) -> usize {
+        GeneratorTagged::BASE_SIZE
+    }
+
+    fn default_chunk_factor(&self) -> usize {
+        GeneratorTagged::DEFAULT_CHUNK_FACTOR
+    }
+
+    fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom> {
+        Box::new(GeneratorTagged::new(
+            params.key,
+            self.run_id,
+            self.pass,
+            params.stream_id,
+            params.stream_count,
+            params.chunk_factor,
+        ))
+    }
+}
+
+/// The registered custom generators.
+static REGISTRY: RwLock<Vec<&'static dyn GeneratorFactory>> = RwLock::new(Vec::new());
+
+/// Register a custom generator.
+/// Returns the stream type, that selects the generator.
+/// The generator can then also be selected by its name.
+pub fn register_generator(factory: Box<dyn GeneratorFactory>) -> ah::Result<DtStreamType> {
+    let name = factory.name();
+    if name.is_empty() || name.contains(':') {
+        return Err(ah::format_err!("Invalid generator name '{}'.", name));
+    }
+    if factory.base_size() == 0 || factory.default_chunk_factor() == 0 {
+        return Err(ah::format_err!(
+            "The generator '{}' has an invalid chunk size.",
+            name
+        ));
+    }
+    // Check the name and insert the generator under one lock,
+    // so that concurrent registrations cannot both pass the check.
+    let mut registry = REGISTRY.write().expect("Generator registry lock poison");
+    if !matches!(DtStreamType::parse_builtin(name), Ok(None))
+        || registry
+            .iter()
+            .any(|other| other.name().eq_ignore_ascii_case(name))
+    {
+        return Err(ah::format_err!(
+            "A generator with the name '{}' already exists.",
+            name
+        ));
+    }
+    let factory: &'static dyn GeneratorFactory = Box::leak(factory);
+    registry.push(factory);
+    Ok(DtStreamType::Custom(DtCustom::new(factory)))
+}
+
+/// Find a registered custom generator by its name.
+pub fn find_generator(name: &str) -> Option<DtStreamType> {
+    REGISTRY
+        .read()
+        .expect("Generator registry lock poison")
+        .iter()
+        .find(|factory| factory.name().eq_ignore_ascii_case(name))
+        .map(|&factory| DtStreamType::Custom(DtCustom::new(factory)))
+}
+
+/// Get all registered custom generators.
+pub fn custom_generators() -> Vec<DtStreamType> {
+    REGISTRY
+        .read()
+        .expect("Generator registry lock poison")
+        .iter()
+        .map(|&factory| DtStreamType::Custom(DtCustom::new(factory)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::GeneratorPattern;
+
+    struct TestFactory(&'static str);
+
+    impl GeneratorFactory for TestFactory {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn base_size(&self) -> usize {
+            GeneratorPattern::base_size(1)
+        }
+
+        fn default_chunk_factor(&self) -> usize {
+            4
+        }
+
+        fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom> {
+            Box::new(GeneratorPattern::new(&[params.stream_id as u8]))
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let stype = register_generator(Box::new(TestFactory("REGTEST"))).unwrap();
+        assert_eq!(stype.to_string(), "REGTEST");
+        assert_eq!("regtest".parse::<DtStreamType>().unwrap(), stype);
+        assert_eq!(find_generator("REGTEST"), Some(stype));
+        assert!(custom_generators().contains(&stype));
+        assert_eq!(stype.pattern(), None);
+
+        // Duplicate and invalid names are refused.
+        assert!(register_generator(Box::new(TestFactory("REGTEST"))).is_err());
+        assert!(register_generator(Box::new(TestFactory("CHACHA20"))).is_err());
+        assert!(register_generator(Box::new(TestFactory("PATTERN:00"))).is_err());
+        assert!(register_generator(Box::new(TestFactory(""))).is_err());
+    }
+
+    #[test]
+    fn test_register_concurrent() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| register_generator(Box::new(TestFactory("REGRACE"))).is_ok())
+            })
+            .collect();
+        let registered = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|ok| *ok)
+            .count();
+        assert_eq!(registered, 1);
+        assert_eq!(
+            custom_generators()
+                .iter()
+                .filter(|stype| stype.to_string() == "REGRACE")
+                .count(),
+            1
+        );
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
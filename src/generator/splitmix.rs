@@ -0,0 +1,166 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::NextRandom;
+use crate::secret::{wipe, wipe_value};
+use crate::util::fold;
+use anyhow as ah;
+
+/// Counter based SplitMix64 generator.
+/// Every output word only depends on the seed and the word index.
+/// That makes seeking O(1) and lets the compiler vectorize the inner loop.
+/// Not cryptographically secure.
+pub struct GeneratorSplitMix {
+    key: u64,
+    /// Index of the next output word.
+    counter: u64,
+}
+
+impl GeneratorSplitMix {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = 1024 * 2;
+    /// Default chunk size multiplicator.
+    pub const DEFAULT_CHUNK_FACTOR: usize = 1024 + 512;
+
+    const WORD_SIZE: usize = 64 / 8;
+    const GAMMA: u64 = 0x9E3779B97F4A7C15;
+
+    pub fn new(seed: &[u8]) -> GeneratorSplitMix {
+        assert!(!seed.is_empty());
+        let mut key = [0u8; GeneratorSplitMix::WORD_SIZE];
+        key.copy_from_slice(&fold(seed, GeneratorSplitMix::WORD_SIZE));
+        let gen = GeneratorSplitMix {
+            key: u64::from_le_bytes(key),
+            counter: 0,
+        };
+        wipe(&mut key);
+        gen
+    }
+
+    /// The SplitMix64 output function.
+    #[inline(always)]
+    pub fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl Drop for GeneratorSplitMix {
+    fn drop(&mut self) {
+        wipe_value(&mut self.key, 0);
+    }
+}
+
+impl NextRandom for GeneratorSplitMix {
+    fn get_base_size(&self) -> usize {
+        GeneratorSplitMix::BASE_SIZE
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == GeneratorSplitMix::BASE_SIZE * count);
+
+        // Fast inner loop:
+        // All words are independent of each other.
+        let base = self
+            .key
+            .wrapping_add(self.counter.wrapping_mul(GeneratorSplitMix::GAMMA));
+        for (i, word) in buf
+            .chunks_exact_mut(GeneratorSplitMix::WORD_SIZE)
+            .enumerate()
+        {
+            let z = base.wrapping_add((i as u64 + 1).wrapping_mul(GeneratorSplitMix::GAMMA));
+            word.copy_from_slice(&GeneratorSplitMix::mix(z).to_le_bytes());
+        }
+        self.counter = self
+            .counter
+            .wrapping_add((buf.len() / GeneratorSplitMix::WORD_SIZE) as u64);
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        if !byte_offset.is_multiple_of(GeneratorSplitMix::WORD_SIZE as u64) {
+            return Err(ah::format_err!(
+                "SplitMix seek: Byte offset is not a \
+                 multiple of the word size ({} bytes).",
+                GeneratorSplitMix::WORD_SIZE
+            ));
+        }
+
+        self.counter = byte_offset / GeneratorSplitMix::WORD_SIZE as u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_result() {
+        // Reference SplitMix64 sequence for the state 1234567.
+        let mut a = GeneratorSplitMix {
+            key: 1234567,
+            counter: 0,
+        };
+        let mut buf = vec![0u8; GeneratorSplitMix::BASE_SIZE];
+        a.next(&mut buf, 1);
+        let word = |i: usize| u64::from_le_bytes(buf[i * 8..(i + 1) * 8].try_into().unwrap());
+        assert_eq!(word(0), 6457827717110365317);
+        assert_eq!(word(1), 3203168211198807973);
+        assert_eq!(word(2), 9817491932198370423);
+    }
+
+    #[test]
+    fn test_seed_diff() {
+        let mut a = GeneratorSplitMix::new(&[1, 2, 3]);
+        let mut b = GeneratorSplitMix::new(&[1, 2, 3]);
+        let mut c = GeneratorSplitMix::new(&[1, 2, 4]);
+        let mut buf_a = vec![0u8; GeneratorSplitMix::BASE_SIZE];
+        let mut buf_b = vec![0u8; GeneratorSplitMix::BASE_SIZE];
+        let mut buf_c = vec![0u8; GeneratorSplitMix::BASE_SIZE];
+        a.next(&mut buf_a, 1);
+        b.next(&mut buf_b, 1);
+        c.next(&mut buf_c, 1);
+        assert_eq!(buf_a, buf_b);
+        assert_ne!(buf_a, buf_c);
+        a.next(&mut buf_b, 1);
+        assert_ne!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut a = GeneratorSplitMix::new(&[1, 2, 3]);
+        let mut b = GeneratorSplitMix::new(&[1, 2, 3]);
+        let mut adata = vec![0u8; GeneratorSplitMix::BASE_SIZE * 3];
+        a.next(&mut adata, 3);
+        let mut bdata = vec![0u8; GeneratorSplitMix::BASE_SIZE];
+        b.seek(GeneratorSplitMix::BASE_SIZE as u64 * 2).unwrap();
+        b.next(&mut bdata, 1);
+        assert_eq!(&adata[GeneratorSplitMix::BASE_SIZE * 2..], &bdata[..]);
+        b.seek(24).unwrap();
+        b.next(&mut bdata, 1);
+        assert_eq!(&adata[24..24 + GeneratorSplitMix::BASE_SIZE], &bdata[..]);
+        assert!(b.seek(25).is_err());
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,141 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::generator::{GeneratorChaCha20, NextRandom};
+use crate::tag::{SectorTag, TAG_BLOCK_SIZE};
+use anyhow as ah;
+
+/// ChaCha20 generator that starts every block with a self-describing tag.
+pub struct GeneratorTagged {
+    payload: GeneratorChaCha20,
+    run_id: u64,
+    pass: u32,
+    thread_id: u64,
+    num_threads: u64,
+    chunk_size: u64,
+    /// Byte offset in the stream of this generator.
+    offset: u64,
+}
+
+impl GeneratorTagged {
+    /// Size of the algorithm base output data.
+    pub const BASE_SIZE: usize = GeneratorChaCha20::BASE_SIZE;
+    /// Default chunk size multiplicator.
+    pub const DEFAULT_CHUNK_FACTOR: usize = GeneratorChaCha20::DEFAULT_CHUNK_FACTOR;
+
+    /// Create a new tagged generator.
+    /// thread_id, num_threads, chunk_factor: The stream aggregation parameters.
+    /// They are needed to calculate the absolute address of each block.
+    pub fn new(
+        seed: &[u8],
+        run_id: u64,
+        pass: u32,
+        thread_id: u32,
+        num_threads: u32,
+        chunk_factor: usize,
+    ) -> GeneratorTagged {
+        assert!(thread_id < num_threads);
+        assert!(chunk_factor > 0);
+
+        GeneratorTagged {
+            payload: GeneratorChaCha20::new(seed),
+            run_id,
+            pass,
+            thread_id: thread_id as u64,
+            num_threads: num_threads as u64,
+            chunk_size: (GeneratorTagged::BASE_SIZE * chunk_factor) as u64,
+            offset: 0,
+        }
+    }
+
+    /// Convert an offset in the stream of this generator
+    /// to an absolute offset in the aggregated stream.
+    fn absolute_offset(&self, offset: u64) -> u64 {
+        let iteration = offset / self.chunk_size;
+        let chunk_index = iteration * self.num_threads + self.thread_id;
+        chunk_index * self.chunk_size + offset % self.chunk_size
+    }
+}
+
+impl NextRandom for GeneratorTagged {
+    fn get_base_size(&self) -> usize {
+        GeneratorTagged::BASE_SIZE
+    }
+
+    fn next(&mut self, buf: &mut [u8], count: usize) {
+        debug_assert!(buf.len() == GeneratorTagged::BASE_SIZE * count);
+
+        self.payload.next(buf, count);
+        for block in buf.chunks_exact_mut(TAG_BLOCK_SIZE) {
+            let tag = SectorTag {
+                run_id: self.run_id,
+                lba: self.absolute_offset(self.offset) / TAG_BLOCK_SIZE as u64,
+                pass: self.pass,
+            };
+            tag.encode(block);
+            self.offset += TAG_BLOCK_SIZE as u64;
+        }
+    }
+
+    fn seek(&mut self, byte_offset: u64) -> ah::Result<()> {
+        self.payload.seek(byte_offset)?;
+        self.offset = byte_offset;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::TAG_SIZE;
+
+    #[test]
+    fn test_tags() {
+        let chunk_size = GeneratorTagged::BASE_SIZE * 2;
+        let mut a = GeneratorTagged::new(&[1, 2, 3], 42, 1, 1, 3, 2);
+        a.seek(chunk_size as u64).unwrap();
+        let mut buf = vec![0u8; chunk_size];
+        a.next(&mut buf, 2);
+
+        // Second chunk of thread 1 of 3 is the 5th chunk of the aggregated stream.
+        let first_lba = (chunk_size * 4 / TAG_BLOCK_SIZE) as u64;
+        for (i, block) in buf.chunks(TAG_BLOCK_SIZE).enumerate() {
+            let (tag, inverted) = SectorTag::decode(block).unwrap();
+            assert!(!inverted);
+            assert_eq!(tag.run_id, 42);
+            assert_eq!(tag.pass, 1);
+            assert_eq!(tag.lba, first_lba + i as u64);
+        }
+
+        // The payload is ChaCha20.
+        let mut b = GeneratorChaCha20::new(&[1, 2, 3]);
+        b.seek(chunk_size as u64).unwrap();
+        let mut payload = vec![0u8; chunk_size];
+        b.next(&mut payload, 2);
+        assert_eq!(
+            &buf[TAG_SIZE..TAG_BLOCK_SIZE],
+            &payload[TAG_SIZE..TAG_BLOCK_SIZE]
+        );
+        assert_ne!(&buf[..TAG_SIZE], &payload[..TAG_SIZE]);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
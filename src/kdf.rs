@@ -19,10 +19,28 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
-use ring::{digest, pbkdf2};
+use crate::secret::{wipe, DtSecret};
+use crate::stream::DtKeySchedule;
+use ring::{digest, hkdf, pbkdf2};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::thread;
 
 const ITERATIONS: u32 = 50000;
 const DK_SIZE: usize = 256 / 8;
+const MASTER_KEY_SIZE: usize = 512 / 8;
+const SEED_ID_KEY_SIZE: usize = 256 / 8;
+/// Number of bytes in a fingerprint.
+const FINGERPRINT_LEN: usize = 8;
+
+/// Output length of the HKDF expansion.
+struct OkmLen(usize);
+
+impl hkdf::KeyType for OkmLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
 
 /// Generate a bad salt substitution from the key.
 fn derive_salt(key: &[u8]) -> [u8; 512 / 8] {
@@ -36,28 +54,225 @@ fn derive_salt(key: &[u8]) -> [u8; 512 / 8] {
     salt
 }
 
-/// Key derivation function for the user supplied seed.
-pub fn kdf(seed: &[u8], thread_id: u32) -> Vec<u8> {
-    // The key is: SEED | THREAD_ID
-    let mut key = seed.to_vec();
-    key.extend_from_slice(&thread_id.to_le_bytes());
+/// Run PBKDF2 on SEED | SUFFIX and write the derived key to `out`.
+/// The key and the salt are wiped afterwards.
+fn pbkdf2_derive(seed: &[u8], suffix: &[u8], out: &mut [u8]) {
+    let mut key = DtSecret::new(seed.len() + suffix.len());
+    key[..seed.len()].copy_from_slice(seed);
+    key[seed.len()..].copy_from_slice(suffix);
 
-    // Calculated the DK (derived key).
-    let mut dk = vec![0; DK_SIZE];
+    // Calculate the DK (derived key).
+    let mut salt = derive_salt(&key);
     pbkdf2::derive(
         pbkdf2::PBKDF2_HMAC_SHA512,
         ITERATIONS.try_into().unwrap(),
-        &derive_salt(&key),
+        &salt,
         &key,
-        &mut dk,
+        out,
     );
-    dk
+    wipe(&mut salt);
+}
+
+/// Key derivation function for the user supplied seed.
+/// Writes the DK (derived key) of one thread to `dk`.
+/// This is the key schedule V1.
+fn kdf_into(seed: &[u8], thread_id: u32, dk: &mut [u8]) {
+    // The key is: SEED | THREAD_ID
+    pbkdf2_derive(seed, &thread_id.to_le_bytes(), dk);
+}
+
+/// Key derivation function for the master key of the user supplied seed.
+/// This is the slow part of the key schedule V2.
+/// It is only calculated once per seed.
+/// The master key is returned in a locked and wiped buffer.
+pub fn kdf_master(seed: &[u8]) -> DtSecret {
+    // The key is: SEED | "master"
+    let mut master = DtSecret::new(MASTER_KEY_SIZE);
+    pbkdf2_derive(seed, b"master", &mut master);
+    master
+}
+
+/// Expand the output key material for the info from a pseudorandom key.
+fn hkdf_expand(prk: &[u8], info: &[&[u8]], out: &mut [u8]) {
+    let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA512, prk);
+    prk.expand(info, OkmLen(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .expect("HKDF expand failed");
+}
+
+/// Expand the key of one thread from the master key and write it to `dk`.
+/// This is the cheap part of the key schedule V2.
+fn kdf_expand_into(master: &[u8], thread_id: u32, dk: &mut [u8]) {
+    let info = thread_id.to_le_bytes();
+    hkdf_expand(master, &[b"disktest thread key", &info], dk);
+}
+
+/// Public identifiers of a seed.
+/// They are expanded from the generator keys of the seed,
+/// so that guessing the seed from an identifier costs as much
+/// as guessing it from the generated data.
+/// The identifiers depend on the key schedule of the keys.
+pub struct DtSeedId {
+    /// Key of the identifiers, expanded from the key with the ID 0.
+    key: DtSecret,
+}
+
+impl DtSeedId {
+    /// Expand the identifiers of the seed from its generator keys.
+    /// This does not run the slow KDF again.
+    pub fn new(keys: &DtKeySet) -> DtSeedId {
+        let mut key = DtSecret::new(SEED_ID_KEY_SIZE);
+        hkdf_expand(keys.key(0), &[b"disktest seed id"], &mut key);
+        DtSeedId { key }
+    }
+
+    /// Get the run ID.
+    /// The run ID is written to the block tags of the tagged stream
+    /// to identify the data of other runs.
+    pub fn run_id(&self) -> u64 {
+        let mut id = [0; 8];
+        hkdf_expand(&self.key, &[b"disktest run id"], &mut id);
+        u64::from_le_bytes(id)
+    }
+
+    /// Expand a fingerprint for the label and the parameters as hex string.
+    fn fingerprint_of(&self, label: &[u8], params: &[&dyn Display]) -> String {
+        // Prefix every parameter with its length, so that the encoding is unambiguous.
+        let mut info = vec![];
+        for param in params {
+            let param = param.to_string();
+            info.extend_from_slice(&(param.len() as u64).to_le_bytes());
+            info.extend_from_slice(param.as_bytes());
+        }
+        let mut fingerprint = [0; FINGERPRINT_LEN];
+        hkdf_expand(&self.key, &[label, &info], &mut fingerprint);
+        fingerprint.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Get a short fingerprint of the seed.
+    /// The fingerprint identifies the seed without revealing it.
+    pub fn fingerprint(&self) -> String {
+        self.fingerprint_of(b"disktest seed fingerprint", &[])
+    }
+
+    /// Get a short fingerprint of the seed and the stream parameters.
+    /// Runs with the same fingerprint generate the same data,
+    /// so a verify run can be matched to its write run without revealing the seed.
+    pub fn stream_fingerprint(&self, params: &[&dyn Display]) -> String {
+        self.fingerprint_of(b"disktest stream fingerprint", params)
+    }
+}
+
+/// The derived generator keys of all threads of one seed.
+/// The keys are derived once and shared by all streams and phases.
+pub struct DtKeySet {
+    /// Hash of the seed, that identifies the seed of the keys.
+    seed_hash: digest::Digest,
+    key_schedule: DtKeySchedule,
+    /// The keys of all IDs in one locked buffer.
+    keys: DtSecret,
+}
+
+impl DtKeySet {
+    /// Derive the keys with the IDs 0..count from the seed.
+    /// The slow KDF runs in parallel on all CPUs.
+    pub fn derive(seed: &[u8], key_schedule: DtKeySchedule, count: u32) -> DtKeySet {
+        assert!(count > 0);
+
+        let mut keys = DtSecret::new(count as usize * DK_SIZE);
+        match key_schedule {
+            DtKeySchedule::V1 => {
+                let nr_workers = thread::available_parallelism()
+                    .map_or(1, |n| n.get())
+                    .min(count as usize);
+                let per_worker = (count as usize).div_ceil(nr_workers);
+                thread::scope(|s| {
+                    for (i, worker_keys) in keys.chunks_mut(per_worker * DK_SIZE).enumerate() {
+                        s.spawn(move || {
+                            for (j, key) in worker_keys.chunks_mut(DK_SIZE).enumerate() {
+                                kdf_into(seed, (i * per_worker + j) as u32, key);
+                            }
+                        });
+                    }
+                });
+            }
+            DtKeySchedule::V2 => {
+                let master = kdf_master(seed);
+                for (id, key) in keys.chunks_mut(DK_SIZE).enumerate() {
+                    kdf_expand_into(&master, id as u32, key);
+                }
+            }
+        }
+        DtKeySet {
+            seed_hash: DtKeySet::hash_seed(seed),
+            key_schedule,
+            keys,
+        }
+    }
+
+    fn hash_seed(seed: &[u8]) -> digest::Digest {
+        let mut hash = digest::Context::new(&digest::SHA512);
+        hash.update(b"disktest key set");
+        hash.update(seed);
+        hash.finish()
+    }
+
+    /// Check whether the key set contains the keys 0..count of the seed.
+    pub fn matches(&self, seed: &[u8], key_schedule: DtKeySchedule, count: u32) -> bool {
+        self.key_schedule == key_schedule
+            && self.keys.len() >= count as usize * DK_SIZE
+            && self.seed_hash.as_ref() == DtKeySet::hash_seed(seed).as_ref()
+    }
+
+    /// Get the cached key set, if it matches. Otherwise derive and cache a new one.
+    pub fn get_or_derive(
+        cache: &mut Option<Arc<DtKeySet>>,
+        seed: &[u8],
+        key_schedule: DtKeySchedule,
+        count: u32,
+    ) -> Arc<DtKeySet> {
+        match cache {
+            Some(keys) if keys.matches(seed, key_schedule, count) => Arc::clone(keys),
+            _ => Arc::clone(cache.insert(Arc::new(DtKeySet::derive(seed, key_schedule, count)))),
+        }
+    }
+
+    /// Get the key with the ID.
+    pub fn key(&self, key_id: u32) -> &[u8] {
+        let begin = key_id as usize * DK_SIZE;
+        &self.keys[begin..begin + DK_SIZE]
+    }
+
+    /// Get the key of the map of the duplicate chunks.
+    /// It is expanded from the key with the ID 0 with its own label,
+    /// so that the positions of the duplicates reveal nothing about the generator keys.
+    pub fn dedup_key(&self) -> u64 {
+        let mut key = [0; 8];
+        hkdf_expand(self.key(0), &[b"disktest dedup key"], &mut key);
+        let dedup_key = u64::from_le_bytes(key);
+        wipe(&mut key);
+        dedup_key
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Key derivation function for the user supplied seed.
+    /// Returns the DK (derived key) of one thread.
+    fn kdf(seed: &[u8], thread_id: u32) -> Vec<u8> {
+        let mut dk = vec![0; DK_SIZE];
+        kdf_into(seed, thread_id, &mut dk);
+        dk
+    }
+
+    fn kdf_expand(master: &[u8], thread_id: u32) -> Vec<u8> {
+        let mut dk = vec![0; DK_SIZE];
+        kdf_expand_into(master, thread_id, &mut dk);
+        dk
+    }
+
     #[test]
     fn test_salt() {
         assert_eq!(
@@ -95,6 +310,103 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_kdf_expand() {
+        let master = kdf_master(&[1, 2, 3]);
+        assert_eq!(master.len(), MASTER_KEY_SIZE);
+        assert_eq!(master, kdf_master(&[1, 2, 3]));
+        assert_ne!(master, kdf_master(&[1, 2, 4]));
+        assert_eq!(
+            kdf_expand(&master, 42),
+            [
+                36, 8, 97, 82, 86, 191, 207, 235, 172, 105, 220, 64, 158, 186, 109, 129, 205, 3,
+                15, 104, 213, 133, 85, 247, 219, 141, 164, 6, 82, 44, 228, 78
+            ]
+        );
+        assert_eq!(
+            kdf_expand(&master, 43),
+            [
+                118, 235, 127, 252, 42, 84, 211, 73, 153, 249, 129, 53, 46, 212, 111, 93, 164, 118,
+                73, 37, 218, 3, 142, 5, 52, 176, 178, 96, 157, 27, 147, 137
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seed_id() {
+        let seed_id = |seed: &[u8], key_schedule, count| {
+            DtSeedId::new(&DtKeySet::derive(seed, key_schedule, count))
+        };
+        let id = seed_id(&[1, 2, 3], DtKeySchedule::V2, 1);
+        assert_eq!(
+            id.run_id(),
+            seed_id(&[1, 2, 3], DtKeySchedule::V2, 1).run_id()
+        );
+        assert_ne!(
+            id.run_id(),
+            seed_id(&[1, 2, 4], DtKeySchedule::V2, 1).run_id()
+        );
+        // The ID key is not a generator key.
+        let keys = DtKeySet::derive(&[1, 2, 3], DtKeySchedule::V2, 1);
+        assert_ne!(&id.key[..], keys.key(0));
+
+        let fp = id.fingerprint();
+        assert_eq!(fp.len(), FINGERPRINT_LEN * 2);
+        assert_eq!(fp, DtSeedId::new(&keys).fingerprint());
+        assert_ne!(fp, seed_id(&[1, 2, 4], DtKeySchedule::V2, 1).fingerprint());
+        // The identifiers do not depend on the number of keys.
+        assert_eq!(fp, seed_id(&[1, 2, 3], DtKeySchedule::V2, 3).fingerprint());
+        assert_eq!(
+            seed_id(&[1, 2, 3], DtKeySchedule::V1, 1).fingerprint(),
+            seed_id(&[1, 2, 3], DtKeySchedule::V1, 2).fingerprint()
+        );
+        assert_ne!(fp, seed_id(&[1, 2, 3], DtKeySchedule::V1, 1).fingerprint());
+
+        let fp = id.stream_fingerprint(&[&"CHACHA20", &1]);
+        assert_eq!(fp.len(), FINGERPRINT_LEN * 2);
+        assert_eq!(fp, id.stream_fingerprint(&[&"CHACHA20", &1]));
+        assert_ne!(fp, id.stream_fingerprint(&[&"CHACHA20", &2]));
+        assert_ne!(fp, id.stream_fingerprint(&[&"CHACHA201"]));
+        assert_ne!(fp, id.fingerprint());
+        assert_ne!(
+            fp,
+            seed_id(&[1, 2, 4], DtKeySchedule::V2, 1).stream_fingerprint(&[&"CHACHA20", &1])
+        );
+    }
+
+    #[test]
+    fn test_key_set() {
+        for key_schedule in [DtKeySchedule::V1, DtKeySchedule::V2] {
+            let keys = DtKeySet::derive(&[1, 2, 3], key_schedule, 5);
+            let master = kdf_master(&[1, 2, 3]);
+            for id in 0..5 {
+                let expected = match key_schedule {
+                    DtKeySchedule::V1 => kdf(&[1, 2, 3], id),
+                    DtKeySchedule::V2 => kdf_expand(&master, id),
+                };
+                assert_eq!(keys.key(id), &expected[..]);
+            }
+            assert!(keys.matches(&[1, 2, 3], key_schedule, 5));
+            assert!(keys.matches(&[1, 2, 3], key_schedule, 1));
+            assert!(!keys.matches(&[1, 2, 3], key_schedule, 6));
+            assert!(!keys.matches(&[1, 2, 4], key_schedule, 5));
+        }
+        let keys = DtKeySet::derive(&[1, 2, 3], DtKeySchedule::V1, 1);
+        assert!(!keys.matches(&[1, 2, 3], DtKeySchedule::V2, 1));
+
+        // The dedup key only depends on the key with the ID 0.
+        let dedup_key = keys.dedup_key();
+        assert_eq!(
+            dedup_key,
+            DtKeySet::derive(&[1, 2, 3], DtKeySchedule::V1, 3).dedup_key()
+        );
+        assert_ne!(
+            dedup_key,
+            DtKeySet::derive(&[1, 2, 4], DtKeySchedule::V1, 1).dedup_key()
+        );
+        assert_ne!(&dedup_key.to_le_bytes()[..], &keys.key(0)[..8]);
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
@@ -0,0 +1,128 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+//! The disktest engine as a library.
+//!
+//! Custom generators can be registered with [register_generator].
+//! They are then used by the multithreaded stream aggregator
+//! and the write and verify machinery like the built-in algorithms.
+//!
+//! ```
+//! use disktest::{
+//!     register_generator, DisktestQuiet, DtStreamAgg, GeneratorFactory, GeneratorParams,
+//!     NextRandom,
+//! };
+//!
+//! /// A (weak) scrambler, that only depends on the key and the byte offset.
+//! struct Scrambler {
+//!     key: u8,
+//!     offset: u64,
+//! }
+//!
+//! impl NextRandom for Scrambler {
+//!     fn get_base_size(&self) -> usize {
+//!         4096
+//!     }
+//!
+//!     fn next(&mut self, buf: &mut [u8], _count: usize) {
+//!         for x in buf.iter_mut() {
+//!             *x = self.key ^ (self.offset as u8).rotate_left(3);
+//!             self.offset += 1;
+//!         }
+//!     }
+//!
+//!     fn seek(&mut self, byte_offset: u64) -> anyhow::Result<()> {
+//!         self.offset = byte_offset;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! struct ScramblerFactory;
+//!
+//! impl GeneratorFactory for ScramblerFactory {
+//!     fn name(&self) -> &str {
+//!         "SCRAMBLER"
+//!     }
+//!
+//!     fn base_size(&self) -> usize {
+//!         4096
+//!     }
+//!
+//!     fn default_chunk_factor(&self) -> usize {
+//!         256
+//!     }
+//!
+//!     fn new_generator(&self, params: &GeneratorParams) -> Box<dyn NextRandom> {
+//!         Box::new(Scrambler {
+//!             key: params.key[0],
+//!             offset: 0,
+//!         })
+//!     }
+//! }
+//!
+//! let algorithm = register_generator(Box::new(ScramblerFactory)).unwrap();
+//! assert_eq!("scrambler".parse().ok(), Some(algorithm));
+//!
+//! let mut agg = DtStreamAgg::new(algorithm, b"seed".to_vec(), false, 2, DisktestQuiet::NoWarn);
+//! agg.activate(0, 512).unwrap();
+//! assert_eq!(agg.wait_chunk().unwrap().get_data().len(), 4096 * 256);
+//! ```
+
+mod bufcache;
+mod checkpoint;
+mod dedup;
+mod disktest;
+mod displacement;
+mod dump;
+mod error;
+mod generator;
+mod kdf;
+mod mismatch;
+mod rawio;
+mod report;
+mod secret;
+mod stream;
+mod stream_aggregator;
+mod tag;
+mod util;
+
+pub use crate::checkpoint::{Checkpoint, CheckpointPhase};
+pub use crate::dedup::DedupMap;
+pub use crate::disktest::{
+    Disktest, DisktestFile, DisktestQuiet, DtCompressRatio, DtDedupRatio, DtKeySchedule, DtKeySet,
+    DtSeedId, DtStreamLayout, DtStreamType,
+};
+pub use crate::displacement::{Displacement, DisplacementSource};
+pub use crate::error::{exit_code, DisktestError};
+pub use crate::generator::{
+    custom_generators, register_generator, GeneratorFactory, GeneratorParams, NextRandom,
+};
+pub use crate::mismatch::{
+    MismatchBits, MismatchKind, MismatchMap, MismatchRegion, UnreadableMap, UnreadableRegion,
+};
+pub use crate::rawio::DEFAULT_SECTOR_SIZE;
+pub use crate::report::{PhaseReport, Report, Throughput};
+pub use crate::secret::DtSecret;
+pub use crate::stream::{DtCustom, DtPattern, DtTagged};
+pub use crate::stream_aggregator::{DtStreamAgg, DtStreamAggActivateResult, DtStreamAggChunk};
+pub use crate::util::{parsebytes, prettybytes};
+
+// vim: ts=4 sw=4 expandtab
@@ -20,20 +20,19 @@
 //
 
 mod args;
-mod bufcache;
-mod disktest;
-mod generator;
-mod kdf;
-mod rawio;
+mod bench;
+mod metadata;
 mod seed;
-mod stream;
-mod stream_aggregator;
-mod util;
 
-use crate::seed::print_generated_seed;
+use crate::args::{parse_args, Args};
+use crate::bench::bench;
+use crate::metadata::RunMetadata;
+use crate::seed::{pass_seed, print_generated_seed, save_generated_seed};
 use anyhow as ah;
-use args::{parse_args, Args};
-use disktest::{Disktest, DisktestFile, DisktestQuiet};
+use disktest::{
+    exit_code, prettybytes, Checkpoint, CheckpointPhase, Disktest, DisktestFile, DisktestQuiet,
+    DtSecret, Report,
+};
 use std::env::args_os;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
@@ -54,55 +53,330 @@ fn install_abort_handlers() -> ah::Result<Arc<AtomicBool>> {
     Ok(abort)
 }
 
+/// Parameters of one write/verify pass.
+struct Pass {
+    /// The pass number, starting at 1.
+    number: u32,
+    seed: DtSecret,
+    invert_pattern: bool,
+}
+
+impl Pass {
+    fn new(args: &Args, number: u32) -> Pass {
+        Pass {
+            number,
+            seed: pass_seed(args.seed.as_str(), number),
+            invert_pattern: args.invert_pattern ^ (number & 1 == 0),
+        }
+    }
+
+    fn pattern_name(&self) -> &'static str {
+        if self.invert_pattern {
+            "inverted"
+        } else {
+            "normal"
+        }
+    }
+}
+
+/// What is left to do in a pass.
+struct PassPlan {
+    write: bool,
+    write_seek: u64,
+    write_bytes: u64,
+    verify: bool,
+    verify_seek: u64,
+    verify_bytes: u64,
+}
+
+impl PassPlan {
+    /// Plan a complete pass.
+    fn new(args: &Args) -> PassPlan {
+        PassPlan {
+            write: args.write,
+            write_seek: args.seek,
+            write_bytes: args.max_bytes,
+            verify: args.verify,
+            verify_seek: args.seek,
+            verify_bytes: args.max_bytes,
+        }
+    }
+
+    /// Plan the rest of the interrupted pass of a checkpoint.
+    fn resume(args: &Args, cp: &Checkpoint) -> PassPlan {
+        let mut plan = PassPlan::new(args);
+        match cp.phase {
+            CheckpointPhase::Start => (),
+            CheckpointPhase::Write => {
+                plan.write_seek = cp.offset;
+                plan.write_bytes = cp.remaining_bytes();
+                plan.write = plan.write_bytes > 0;
+            }
+            CheckpointPhase::Verify => {
+                plan.write = false;
+                plan.verify_seek = cp.offset;
+                plan.verify_bytes = cp.remaining_bytes();
+                plan.verify = plan.verify_bytes > 0;
+            }
+            CheckpointPhase::Done => {
+                plan.write = false;
+                plan.verify = false;
+            }
+        }
+        if args.quiet < DisktestQuiet::NoInfo {
+            match cp.phase {
+                CheckpointPhase::Write | CheckpointPhase::Verify => println!(
+                    "Resuming the interrupted {} at position {}.",
+                    if cp.phase == CheckpointPhase::Write {
+                        "write"
+                    } else {
+                        "verification"
+                    },
+                    prettybytes(cp.offset, true, true, false)
+                ),
+                CheckpointPhase::Done => println!("The checkpointed run has already completed."),
+                CheckpointPhase::Start => (),
+            }
+        }
+        plan
+    }
+}
+
 /// Create a new disktest core instance.
 fn new_disktest(
     args: &Args,
+    pass: &Pass,
     write: bool,
     abort: &Arc<AtomicBool>,
 ) -> ah::Result<(Disktest, DisktestFile)> {
-    Ok((
-        Disktest::new(
-            args.algorithm,
-            args.seed.as_bytes().to_vec(),
-            args.invert_pattern,
-            args.threads,
-            args.quiet,
-            Some(Arc::clone(abort)),
-        ),
-        DisktestFile::open(&args.device, !write, write)?,
-    ))
+    let mut disktest = Disktest::new(
+        args.algorithm
+            .with_run(args.seed_id().run_id(), pass.number),
+        pass.seed.clone(),
+        pass.invert_pattern,
+        args.threads,
+        args.quiet,
+        Some(Arc::clone(abort)),
+    );
+    disktest.set_layout(args.layout);
+    disktest.set_compress_ratio(args.compress_ratio);
+    disktest.set_dedup_ratio(args.dedup_ratio);
+    disktest.set_key_schedule(args.key_schedule);
+    disktest.set_keep_going(args.keep_going);
+    disktest.set_skip_read_errors(args.skip_read_errors);
+    disktest.set_hexdump(args.hexdump);
+    disktest.set_dump_dir(args.dump_dir.clone());
+    Ok((disktest, DisktestFile::open(&args.device, !write, write)?))
 }
 
-/// Main program entry point.
-fn main() -> ah::Result<()> {
-    let args = parse_args(args_os())?;
+/// Create the checkpoint of a new run or load the checkpoint of the run to resume.
+fn load_checkpoint(args: &Args) -> ah::Result<Option<Checkpoint>> {
+    let Some(path) = args.checkpoint.as_ref() else {
+        return Ok(None);
+    };
+    let checkpoint = Checkpoint {
+        path: path.clone(),
+        device: args.device.clone(),
+        algorithm: args.algorithm,
+        layout: args.layout,
+        compress_ratio: args.compress_ratio,
+        dedup_ratio: args.dedup_ratio,
+        key_schedule: args.key_schedule,
+        threads: Disktest::resolve_nr_threads(args.threads),
+        seed_fingerprint: args.seed_id().fingerprint(),
+        stream_fingerprint: args.stream_fingerprint(),
+        invert_pattern: args.invert_pattern,
+        write: args.write,
+        verify: args.verify,
+        seek: args.seek,
+        max_bytes: args.max_bytes,
+        passes: args.passes,
+        pass: 1,
+        phase: CheckpointPhase::Start,
+        phase_start: 0,
+        offset: 0,
+    };
+    if !args.resume {
+        checkpoint.save()?;
+        return Ok(Some(checkpoint));
+    }
+
+    let saved = Checkpoint::load(path)?;
+    saved.check_resumable(&checkpoint)?;
+    if saved.device != checkpoint.device && args.quiet < DisktestQuiet::NoWarn {
+        eprintln!(
+            "WARNING: The checkpoint was recorded for device {:?}.",
+            saved.device
+        );
+    }
+    Ok(Some(saved))
+}
+
+/// Run all passes and add their phases to the report.
+fn run_passes(args: &Args, fingerprint: &str, report: &mut Report) -> ah::Result<()> {
     let abort = install_abort_handlers()?;
 
-    if !args.user_seed && args.quiet < DisktestQuiet::NoInfo {
-        print_generated_seed(&args.seed, true);
+    if !args.seed.is_locked() && args.quiet < DisktestQuiet::NoWarn {
+        eprintln!("WARNING: The seed could not be locked into RAM. It may be swapped out.");
     }
 
-    // Run write-mode, if requested.
+    if let (false, Some(path)) = (args.user_seed, args.gen_seed_file.as_ref()) {
+        save_generated_seed(path, args.seed.as_str())?;
+        if args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "\nThe generated seed was written to {:?}.\n\
+                 Use --seed-file for subsequent --verify.\n\
+                 The stream fingerprint is {}.\n",
+                path, fingerprint
+            );
+        }
+    } else if !args.user_seed && args.quiet < DisktestQuiet::NoInfo {
+        print_generated_seed(args.seed.as_str(), fingerprint, true);
+    } else if args.quiet < DisktestQuiet::NoInfo {
+        println!("Stream fingerprint {}.", fingerprint);
+    }
+
+    if let (Some(path), true, false) = (args.metadata.as_ref(), args.write, args.resume) {
+        RunMetadata::new(args).save(path)?;
+    }
+
+    let mut checkpoint = load_checkpoint(args)?;
+    let first_pass = match (args.resume, checkpoint.as_ref()) {
+        (true, Some(cp)) => cp.pass.clamp(1, args.passes),
+        // Verify-only mode verifies the data of the last pass.
+        _ if !args.write => args.passes,
+        _ => 1,
+    };
+
+    // The generator keys are derived once and reused by all phases with the same seed.
+    let mut keys = Some(Arc::clone(args.key_set()));
     let mut result = Ok(());
-    if args.write {
-        let (mut disktest, file) = new_disktest(&args, true, &abort)?;
-        result = match disktest.write(file, args.seek, args.max_bytes) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+    let mut pass_results = vec![];
+    for number in first_pass..=args.passes {
+        let pass = Pass::new(args, number);
+
+        let plan = match checkpoint.as_mut() {
+            Some(cp) if args.resume && number == first_pass => PassPlan::resume(args, cp),
+            Some(cp) => {
+                if cp.pass != number {
+                    cp.pass = number;
+                    cp.phase = CheckpointPhase::Start;
+                    cp.save()?;
+                }
+                PassPlan::new(args)
+            }
+            None => PassPlan::new(args),
         };
+
+        if args.passes > 1 && args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "\nPass {} of {} with {} pattern.",
+                number,
+                args.passes,
+                pass.pattern_name()
+            );
+        }
+
+        // Run write-mode, if requested.
+        if plan.write {
+            let (mut disktest, file) = new_disktest(args, &pass, true, &abort)?;
+            if let Some(keys) = keys.take() {
+                disktest.set_key_set(keys);
+            }
+            disktest.set_checkpoint(checkpoint.take());
+            let res = disktest.write(file, plan.write_seek, plan.write_bytes);
+            checkpoint = disktest.take_checkpoint();
+            keys = disktest.get_key_set();
+            let mut phase = disktest.phase_report("write", &res);
+            phase.pass = number;
+            report.add_phase(phase);
+            result = res.map(|_| ());
+        }
+
+        // Run verify-mode, if requested.
+        if plan.verify && result.is_ok() {
+            let (mut disktest, file) = new_disktest(args, &pass, false, &abort)?;
+            if let Some(keys) = keys.take() {
+                disktest.set_key_set(keys);
+            }
+            disktest.set_checkpoint(checkpoint.take());
+            let res = disktest.verify(file, plan.verify_seek, plan.verify_bytes);
+            checkpoint = disktest.take_checkpoint();
+            keys = disktest.get_key_set();
+            let mut phase = disktest.phase_report("verify", &res);
+            phase.pass = number;
+            report.add_phase(phase);
+            result = res.map(|_| ());
+        }
+
+        pass_results.push(match &result {
+            Ok(_) => (pass, "Success".to_string()),
+            Err(e) => (pass, format!("FAILED: {}", e)),
+        });
+        if result.is_err() {
+            break;
+        }
     }
 
-    // Run verify-mode, if requested.
-    if args.verify && result.is_ok() {
-        let (mut disktest, file) = new_disktest(&args, false, &abort)?;
-        result = match disktest.verify(file, args.seek, args.max_bytes) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        };
+    if args.passes > 1 && args.quiet < DisktestQuiet::NoInfo {
+        println!("\nPass results:");
+        for (pass, res) in &pass_results {
+            println!(
+                "    Pass {} ({} pattern): {}",
+                pass.number,
+                pass.pattern_name(),
+                res
+            );
+        }
+        println!();
+    }
+
+    if let (true, Some(cp)) = (result.is_ok(), checkpoint.as_mut()) {
+        cp.phase = CheckpointPhase::Done;
+        if let Err(e) = cp.save() {
+            result = Err(e);
+        }
     }
 
-    if !args.user_seed && args.quiet < DisktestQuiet::NoInfo {
-        print_generated_seed(&args.seed, false);
+    result
+}
+
+/// Run the program.
+fn run() -> ah::Result<()> {
+    let args = parse_args(args_os())?;
+    if args.bench {
+        let device = Some(args.device.as_path()).filter(|d| !d.as_os_str().is_empty());
+        return bench(
+            &args.bench_algorithms,
+            Disktest::resolve_nr_threads(args.threads),
+            args.bench_target,
+            device,
+        );
+    }
+
+    // The report is written on every exit path after the argument parsing.
+    let fingerprint = args.stream_fingerprint();
+    let mut report = Report::new(
+        &args.device,
+        args.algorithm,
+        args.threads,
+        args.seed_id().fingerprint(),
+        fingerprint.clone(),
+    );
+    let mut result = run_passes(&args, &fingerprint, &mut report);
+
+    if let Some(path) = args.report.as_ref() {
+        if let Err(e) = report.write(path, &result) {
+            if result.is_ok() {
+                result = Err(e);
+            } else if args.quiet < DisktestQuiet::NoWarn {
+                eprintln!("WARNING: {}", e);
+            }
+        }
+    }
+
+    if !args.user_seed && args.gen_seed_file.is_none() && args.quiet < DisktestQuiet::NoInfo {
+        print_generated_seed(args.seed.as_str(), &fingerprint, false);
     }
     if result.is_ok() && args.quiet == DisktestQuiet::Normal {
         println!("Success!");
@@ -111,4 +385,12 @@ fn main() -> ah::Result<()> {
     result
 }
 
+/// Main program entry point.
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
 // vim: ts=4 sw=4 expandtab
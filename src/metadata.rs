@@ -0,0 +1,396 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::args::Args;
+use anyhow as ah;
+use disktest::{
+    Disktest, DisktestError, DisktestQuiet, DtCompressRatio, DtDedupRatio, DtKeySchedule,
+    DtStreamLayout, DtStreamType,
+};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Version of the run metadata file format.
+const METADATA_FORMAT_VERSION: u64 = 1;
+
+/// Get the name of this host.
+#[cfg(not(target_os = "windows"))]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: The buffer is valid for buf.len() bytes.
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+/// Get the name of this host.
+#[cfg(target_os = "windows")]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Format seconds since the UNIX epoch as UTC date and time.
+pub fn utc_datetime(secs: u64) -> String {
+    // Civil date from the number of days since the epoch.
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
+/// Parameters of a write run, that are needed to verify the written data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunMetadata {
+    pub algorithm: DtStreamType,
+    pub layout: DtStreamLayout,
+    pub compress_ratio: DtCompressRatio,
+    pub dedup_ratio: DtDedupRatio,
+    pub key_schedule: DtKeySchedule,
+    /// The actual number of threads.
+    pub threads: usize,
+    pub invert_pattern: bool,
+    /// The --seek argument of the run.
+    pub seek: u64,
+    /// The --bytes argument of the run.
+    pub max_bytes: u64,
+    /// The --passes argument of the run.
+    pub passes: u32,
+    pub seed_fingerprint: String,
+    /// Start of the run, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub host: String,
+}
+
+impl RunMetadata {
+    /// Get the metadata of a write run with the given arguments.
+    pub fn new(args: &Args) -> RunMetadata {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        RunMetadata {
+            algorithm: args.algorithm,
+            layout: args.layout,
+            compress_ratio: args.compress_ratio,
+            dedup_ratio: args.dedup_ratio,
+            key_schedule: args.key_schedule,
+            threads: Disktest::resolve_nr_threads(args.threads),
+            invert_pattern: args.invert_pattern,
+            seek: args.seek,
+            max_bytes: args.max_bytes,
+            passes: args.passes,
+            seed_fingerprint: args.seed_id().fingerprint(),
+            timestamp,
+            host: hostname(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "format_version": METADATA_FORMAT_VERSION,
+            "disktest_version": env!("CARGO_PKG_VERSION"),
+            "algorithm": self.algorithm.to_string(),
+            "layout": self.layout.to_string(),
+            "compress_ratio": self.compress_ratio.to_string(),
+            "dedup_ratio": self.dedup_ratio.to_string(),
+            "key_schedule": self.key_schedule.to_string(),
+            "threads": self.threads,
+            "invert_pattern": self.invert_pattern,
+            "seek": self.seek,
+            "max_bytes": self.max_bytes,
+            "passes": self.passes,
+            "seed_fingerprint": self.seed_fingerprint,
+            "timestamp": self.timestamp,
+            "host": self.host,
+        })
+    }
+
+    fn from_json(json: &Value) -> ah::Result<RunMetadata> {
+        let err = |name| ah::format_err!("Run metadata field '{}' is missing or invalid.", name);
+        let get_u64 = |name| json[name].as_u64().ok_or_else(|| err(name));
+        let get_bool = |name| json[name].as_bool().ok_or_else(|| err(name));
+        let get_str = |name| json[name].as_str().ok_or_else(|| err(name));
+
+        let version = get_u64("format_version")?;
+        if version != METADATA_FORMAT_VERSION {
+            return Err(ah::format_err!(
+                "Unsupported run metadata format version {}.",
+                version
+            ));
+        }
+        Ok(RunMetadata {
+            algorithm: get_str("algorithm")?.parse()?,
+            layout: get_str("layout")?.parse()?,
+            compress_ratio: get_str("compress_ratio")?.parse()?,
+            dedup_ratio: get_str("dedup_ratio")?.parse()?,
+            key_schedule: get_str("key_schedule")?.parse()?,
+            threads: get_u64("threads")? as usize,
+            invert_pattern: get_bool("invert_pattern")?,
+            seek: get_u64("seek")?,
+            max_bytes: get_u64("max_bytes")?,
+            passes: get_u64("passes")? as u32,
+            seed_fingerprint: get_str("seed_fingerprint")?.to_string(),
+            timestamp: get_u64("timestamp")?,
+            host: get_str("host")?.to_string(),
+        })
+    }
+
+    /// Load a run metadata file.
+    pub fn load(path: &Path) -> ah::Result<RunMetadata> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ah::format_err!("Failed to read run metadata {:?}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&text)
+            .map_err(|e| ah::format_err!("Failed to parse run metadata {:?}: {}", path, e))?;
+        RunMetadata::from_json(&json)
+    }
+
+    /// Save the run metadata file.
+    pub fn save(&self, path: &Path) -> ah::Result<()> {
+        let mut text = serde_json::to_string_pretty(&self.to_json())?;
+        text.push('\n');
+        std::fs::write(path, text)
+            .map_err(|e| ah::format_err!("Failed to write run metadata {:?}: {}", path, e))
+    }
+
+    /// Configure a verify run with the recorded parameters.
+    /// given: Returns true, if the named option was given on the command line.
+    /// Options that were given on the command line must match the recorded ones.
+    pub fn apply(
+        &self,
+        path: &Path,
+        args: &mut Args,
+        given: impl Fn(&str) -> bool,
+    ) -> ah::Result<()> {
+        let mut diffs = vec![];
+        if given("algorithm") && args.algorithm != self.algorithm {
+            diffs.push(format!("--algorithm {}", self.algorithm));
+        }
+        if given("layout") && args.layout != self.layout {
+            diffs.push(format!("--layout {}", self.layout));
+        }
+        if given("compress-ratio") && args.compress_ratio != self.compress_ratio {
+            diffs.push(format!("--compress-ratio {}", self.compress_ratio));
+        }
+        if given("dedup-ratio") && args.dedup_ratio != self.dedup_ratio {
+            diffs.push(format!("--dedup-ratio {}", self.dedup_ratio));
+        }
+        if given("key-schedule") && args.key_schedule != self.key_schedule {
+            diffs.push(format!("--key-schedule {}", self.key_schedule));
+        }
+        // The indexed layout does not depend on the number of threads.
+        if given("threads")
+            && self.layout == DtStreamLayout::Interleaved
+            && Disktest::resolve_nr_threads(args.threads) != self.threads
+        {
+            diffs.push(format!("--threads {}", self.threads));
+        }
+        if given("invert-pattern") && args.invert_pattern != self.invert_pattern {
+            diffs.push(format!("--invert-pattern {}", self.invert_pattern));
+        }
+        if given("seek") && args.seek != self.seek {
+            diffs.push(format!("--seek {}", self.seek));
+        }
+        if given("bytes") && args.max_bytes != self.max_bytes {
+            diffs.push(format!("--bytes {}", self.max_bytes));
+        }
+
+        args.algorithm = self.algorithm;
+        args.layout = self.layout;
+        args.compress_ratio = self.compress_ratio;
+        args.dedup_ratio = self.dedup_ratio;
+        args.key_schedule = self.key_schedule;
+        if !given("threads") || self.layout == DtStreamLayout::Interleaved {
+            args.threads = self.threads;
+        }
+        args.invert_pattern = self.invert_pattern;
+        args.seek = self.seek;
+        args.max_bytes = self.max_bytes;
+        args.passes = self.passes;
+
+        // The seed fingerprint depends on the recorded key schedule.
+        if self.seed_fingerprint != args.seed_id().fingerprint() {
+            diffs.insert(
+                0,
+                format!(
+                    "--seed with fingerprint {} (the --seed does not match)",
+                    self.seed_fingerprint
+                ),
+            );
+        }
+        if !diffs.is_empty() {
+            return Err(DisktestError::InvalidArguments.msg(format!(
+                "The parameters do not match the run metadata {:?}. Expected: {}",
+                path,
+                diffs.join(", ")
+            )));
+        }
+
+        if args.quiet < DisktestQuiet::NoInfo {
+            println!(
+                "Using the parameters of the run started {} on host '{}': \
+                 --algorithm {} --layout {} --compress-ratio {} --dedup-ratio {} \
+                 --key-schedule {} --threads {}{} --seek {}{}{}",
+                utc_datetime(self.timestamp),
+                self.host,
+                self.algorithm,
+                self.layout,
+                self.compress_ratio,
+                self.dedup_ratio,
+                self.key_schedule,
+                self.threads,
+                if self.invert_pattern {
+                    " --invert-pattern"
+                } else {
+                    ""
+                },
+                self.seek,
+                if self.max_bytes == Disktest::UNLIMITED {
+                    "".to_string()
+                } else {
+                    format!(" --bytes {}", self.max_bytes)
+                },
+                if self.passes > 1 {
+                    format!(" (last of {} passes)", self.passes)
+                } else {
+                    "".to_string()
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_args;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_utc_datetime() {
+        assert_eq!(utc_datetime(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc_datetime(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(utc_datetime(1792185645), "2026-10-16 21:20:45 UTC");
+    }
+
+    #[test]
+    fn test_save_load_apply() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("meta.json");
+        let path_str = path.to_str().unwrap();
+
+        let args = parse_args(vec![
+            "disktest",
+            "-w",
+            "-Sfoo",
+            "-A",
+            "crc",
+            "-j3",
+            "-i",
+            "-s",
+            "4096",
+            "-b",
+            "1000000",
+            "--key-schedule",
+            "V2",
+            "/dev/foo",
+        ])
+        .unwrap();
+        let meta = RunMetadata::new(&args);
+        assert_eq!(meta.threads, 3);
+        assert_eq!(meta.host, hostname());
+        meta.save(&path).unwrap();
+        assert_eq!(RunMetadata::load(&path).unwrap(), meta);
+
+        // The verify run is configured from the metadata.
+        let a = parse_args(vec![
+            "disktest",
+            "-Sfoo",
+            "--metadata",
+            path_str,
+            "/dev/foo",
+        ])
+        .unwrap();
+        assert_eq!(a.algorithm, DtStreamType::Crc);
+        assert_eq!(a.key_schedule, DtKeySchedule::V2);
+        assert_eq!(a.threads, 3);
+        assert!(a.invert_pattern);
+        assert_eq!(a.seek, 4096);
+        assert_eq!(a.max_bytes, 1000000);
+        assert_eq!(a.passes, 1);
+
+        // Mismatching parameters are refused.
+        let e = parse_args(vec![
+            "disktest",
+            "-Sbar",
+            "-j2",
+            "-A",
+            "crc",
+            "--metadata",
+            path_str,
+            "/dev/foo",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "The parameters do not match the run metadata {:?}. \
+                 Expected: --seed with fingerprint {} (the --seed does not match), --threads 3",
+                path, meta.seed_fingerprint
+            )
+        );
+
+        // The fields of the stream parameters are required.
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        for field in ["compress_ratio", "dedup_ratio", "key_schedule"] {
+            let mut broken = json.clone();
+            broken.as_object_mut().unwrap().remove(field);
+            std::fs::write(&path, broken.to_string()).unwrap();
+            assert!(RunMetadata::load(&path).is_err());
+        }
+
+        std::fs::write(&path, "{}").unwrap();
+        assert!(RunMetadata::load(&path).is_err());
+        tdir.close().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,652 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::util::prettybytes;
+
+/// Mismatching bytes that are separated by less than this
+/// number of good bytes are merged into one region.
+/// Random data matches by chance every 256 bytes on average,
+/// so a plain adjacency check would split up corrupted sectors.
+pub const MISMATCH_MERGE_GAP: u64 = 64;
+
+/// Minimum number of flipped bits in the same direction,
+/// before a region is classified as stuck-at.
+/// A few flips in the same direction can also happen by chance.
+pub const MISMATCH_STUCK_MIN_FLIPS: u64 = 4;
+
+/// Maximum number of corrupted regions in a map.
+/// If there are more regions, the merge gap is widened
+/// and the regions are merged into fewer, coarser regions.
+pub const MISMATCH_MAX_REGIONS: usize = 4096;
+
+/// Bit level statistics of corrupted data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MismatchBits {
+    /// Number of bits that read as 1, but were written as 0.
+    pub zero_to_one: u64,
+    /// Number of bits that read as 0, but were written as 1.
+    pub one_to_zero: u64,
+    /// Number of flipped bits per bit position within a byte.
+    /// Index 0 is the least significant bit.
+    pub positions: [u64; 8],
+}
+
+impl MismatchBits {
+    /// Merge the statistics of other into these statistics.
+    fn merge(&mut self, other: &MismatchBits) {
+        self.zero_to_one += other.zero_to_one;
+        self.one_to_zero += other.one_to_zero;
+        for (count, other) in self.positions.iter_mut().zip(other.positions.iter()) {
+            *count += other;
+        }
+    }
+
+    /// Account for one corrupted byte.
+    pub fn add(&mut self, actual: u8, expected: u8) {
+        let diff = actual ^ expected;
+        self.zero_to_one += (diff & actual).count_ones() as u64;
+        self.one_to_zero += (diff & expected).count_ones() as u64;
+        for (bit, count) in self.positions.iter_mut().enumerate() {
+            if diff & (1 << bit) != 0 {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Get the total number of flipped bits.
+    pub fn flipped(&self) -> u64 {
+        self.zero_to_one + self.one_to_zero
+    }
+
+    /// Format the list of affected bit positions.
+    fn positions_str(&self) -> String {
+        let positions: Vec<String> = self
+            .positions
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bit, _)| bit.to_string())
+            .collect();
+        positions.join(",")
+    }
+
+    /// Format the statistics as human readable string.
+    fn summary(&self) -> String {
+        format!(
+            "{} flipped bits (0->1: {}, 1->0: {}) at bit positions {}",
+            self.flipped(),
+            self.zero_to_one,
+            self.one_to_zero,
+            self.positions_str()
+        )
+    }
+}
+
+/// Classification of a corrupted region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// Whole sectors contain unrelated data.
+    /// Typical for a controller returning the wrong sector or stale data.
+    WholeSectors,
+    /// Several bits are flipped and all flips are from 1 to 0.
+    StuckAtZero,
+    /// Several bits are flipped and all flips are from 0 to 1.
+    StuckAtOne,
+    /// Individual bits are flipped in otherwise correct data.
+    /// Typical for failing flash cells or bad media.
+    ScatteredBits,
+}
+
+impl std::fmt::Display for MismatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MismatchKind::WholeSectors => write!(f, "whole sectors wrong"),
+            MismatchKind::StuckAtZero => write!(f, "bits stuck at 0"),
+            MismatchKind::StuckAtOne => write!(f, "bits stuck at 1"),
+            MismatchKind::ScatteredBits => write!(f, "scattered bit flips"),
+        }
+    }
+}
+
+/// A contiguous region of corrupted data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MismatchRegion {
+    /// Absolute byte offset of the first corrupted byte.
+    pub offset: u64,
+    /// Length of the region, in bytes.
+    /// This spans from the first to the last corrupted byte.
+    pub len: u64,
+    /// Number of actually mismatching bytes within the region.
+    pub bad_bytes: u64,
+    /// Bit level statistics of the mismatching bytes.
+    pub bits: MismatchBits,
+}
+
+impl MismatchRegion {
+    /// Get the absolute byte offset after the last corrupted byte.
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+
+    /// Get the first and the last sector number that this region touches.
+    pub fn sectors(&self, sector_size: u32) -> (u64, u64) {
+        let sector_size = sector_size as u64;
+        (self.offset / sector_size, (self.end() - 1) / sector_size)
+    }
+
+    /// Classify the kind of corruption in this region.
+    pub fn kind(&self, sector_size: u32) -> MismatchKind {
+        // Two unrelated random byte streams differ in 255 of 256 bytes
+        // and in half of the bits.
+        // If the region covers at least one sector and looks like that,
+        // then the sector content has been replaced as a whole.
+        let flipped_per_byte = self.bits.flipped() as f64 / self.bad_bytes as f64;
+        let density = self.bad_bytes as f64 / self.len as f64;
+        if self.len >= sector_size as u64 && density > 0.9 && flipped_per_byte > 3.0 {
+            MismatchKind::WholeSectors
+        } else if self.bits.flipped() < MISMATCH_STUCK_MIN_FLIPS {
+            MismatchKind::ScatteredBits
+        } else if self.bits.zero_to_one == 0 {
+            MismatchKind::StuckAtZero
+        } else if self.bits.one_to_zero == 0 {
+            MismatchKind::StuckAtOne
+        } else {
+            MismatchKind::ScatteredBits
+        }
+    }
+}
+
+/// Count the sectors touched by ascending byte ranges.
+/// A sector shared by two ranges is only counted once.
+fn count_sectors(ranges: impl Iterator<Item = (u64, u64)>, sector_size: u32) -> u64 {
+    let sector_size = sector_size as u64;
+    let mut count = 0;
+    let mut prev_last = None;
+    for (begin, end) in ranges {
+        let mut first = begin / sector_size;
+        let last = (end - 1) / sector_size;
+        if let Some(prev_last) = prev_last {
+            if first <= prev_last {
+                first = prev_last + 1;
+            }
+        }
+        if last >= first {
+            count += last - first + 1;
+        }
+        prev_last = Some(last);
+    }
+    count
+}
+
+/// Map of all corrupted regions found during verification.
+pub struct MismatchMap {
+    regions: Vec<MismatchRegion>,
+    bad_bytes: u64,
+    bits: MismatchBits,
+    /// The current merge gap. Widened, if there are too many regions.
+    merge_gap: u64,
+}
+
+impl Default for MismatchMap {
+    fn default() -> MismatchMap {
+        MismatchMap::new()
+    }
+}
+
+impl MismatchMap {
+    pub fn new() -> MismatchMap {
+        MismatchMap {
+            regions: vec![],
+            bad_bytes: 0,
+            bits: Default::default(),
+            merge_gap: MISMATCH_MERGE_GAP,
+        }
+    }
+
+    /// Remove all regions.
+    pub fn clear(&mut self) {
+        self.regions.clear();
+        self.bad_bytes = 0;
+        self.bits = Default::default();
+        self.merge_gap = MISMATCH_MERGE_GAP;
+    }
+
+    /// Get the current merge gap.
+    /// This is bigger than MISMATCH_MERGE_GAP,
+    /// if the regions have been coarsened to limit their number.
+    pub fn merge_gap(&self) -> u64 {
+        self.merge_gap
+    }
+
+    /// Widen the merge gap and merge the existing regions,
+    /// until there are no more than MISMATCH_MAX_REGIONS regions.
+    fn coarsen(&mut self) {
+        while self.regions.len() > MISMATCH_MAX_REGIONS {
+            self.merge_gap *= 2;
+            let mut merged: Vec<MismatchRegion> = Vec::with_capacity(self.regions.len());
+            for region in self.regions.drain(..) {
+                if let Some(last) = merged.last_mut() {
+                    if region.offset - last.end() < self.merge_gap {
+                        last.len = region.end() - last.offset;
+                        last.bad_bytes += region.bad_bytes;
+                        last.bits.merge(&region.bits);
+                        continue;
+                    }
+                }
+                merged.push(region);
+            }
+            self.regions = merged;
+        }
+    }
+
+    /// Add a single corrupted byte at the absolute offset.
+    /// Offsets must be added in ascending order.
+    pub fn add(&mut self, offset: u64, actual: u8, expected: u8) {
+        self.bad_bytes += 1;
+        self.bits.add(actual, expected);
+        if let Some(last) = self.regions.last_mut() {
+            debug_assert!(offset >= last.end());
+            if offset - last.end() < self.merge_gap {
+                last.len = offset - last.offset + 1;
+                last.bad_bytes += 1;
+                last.bits.add(actual, expected);
+                return;
+            }
+        }
+        let mut bits = MismatchBits::default();
+        bits.add(actual, expected);
+        self.regions.push(MismatchRegion {
+            offset,
+            len: 1,
+            bad_bytes: 1,
+            bits,
+        });
+        if self.regions.len() > MISMATCH_MAX_REGIONS {
+            self.coarsen();
+        }
+    }
+
+    /// Compare the data read from disk to the expected data
+    /// and add all mismatching bytes.
+    /// offset: The absolute byte offset of the buffers.
+    /// Returns the number of mismatching bytes found.
+    pub fn add_buffers(&mut self, offset: u64, actual: &[u8], expected: &[u8]) -> u64 {
+        debug_assert_eq!(actual.len(), expected.len());
+        let mut count = 0;
+        for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            if a != e {
+                self.add(offset + i as u64, *a, *e);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Returns true, if no corrupted region has been found.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Get all corrupted regions in ascending order.
+    pub fn regions(&self) -> &[MismatchRegion] {
+        &self.regions
+    }
+
+    /// Get the total number of mismatching bytes.
+    pub fn bad_bytes(&self) -> u64 {
+        self.bad_bytes
+    }
+
+    /// Get the bit level statistics of all mismatching bytes.
+    pub fn bits(&self) -> &MismatchBits {
+        &self.bits
+    }
+
+    /// Get the total number of sectors that contain corrupted data.
+    pub fn bad_sectors(&self, sector_size: u32) -> u64 {
+        count_sectors(
+            self.regions.iter().map(|r| (r.offset, r.end())),
+            sector_size,
+        )
+    }
+
+    /// Print the list of all corrupted regions to the console.
+    pub fn print_report(&self, sector_size: u32) {
+        if self.is_empty() {
+            return;
+        }
+        println!("\nCorrupted regions:");
+        for region in &self.regions {
+            let (first, last) = region.sectors(sector_size);
+            let sectors = if first == last {
+                format!("sector {}", first)
+            } else {
+                format!("sectors {} - {}", first, last)
+            };
+            println!(
+                "    {} - {}: {} bad bytes in {} ({})",
+                region.offset,
+                region.end() - 1,
+                region.bad_bytes,
+                prettybytes(region.len, true, false, false),
+                sectors
+            );
+            println!(
+                "        {}: {}",
+                region.kind(sector_size),
+                region.bits.summary()
+            );
+        }
+        println!(
+            "Total: {} corrupted regions, {} bad bytes, {} bad sectors of {} bytes.",
+            self.regions.len(),
+            self.bad_bytes,
+            self.bad_sectors(sector_size),
+            sector_size
+        );
+        println!("Total: {}.", self.bits.summary());
+        if self.merge_gap > MISMATCH_MERGE_GAP {
+            println!(
+                "Note: There were more than {} corrupted regions. \
+                 Regions separated by less than {} good bytes have been merged.",
+                MISMATCH_MAX_REGIONS, self.merge_gap
+            );
+        }
+        let whole = self
+            .regions
+            .iter()
+            .filter(|r| r.kind(sector_size) == MismatchKind::WholeSectors)
+            .count();
+        println!(
+            "Total: {} regions with whole sectors wrong, {} regions with bit errors.\n",
+            whole,
+            self.regions.len() - whole
+        );
+    }
+}
+
+/// A contiguous region of unreadable sectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnreadableRegion {
+    /// Absolute byte offset of the first unreadable byte.
+    pub offset: u64,
+    /// Length of the region, in bytes.
+    pub len: u64,
+}
+
+impl UnreadableRegion {
+    /// Get the absolute byte offset after the last unreadable byte.
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// Map of all unreadable sectors found during verification.
+pub struct UnreadableMap {
+    regions: Vec<UnreadableRegion>,
+}
+
+impl Default for UnreadableMap {
+    fn default() -> UnreadableMap {
+        UnreadableMap::new()
+    }
+}
+
+impl UnreadableMap {
+    pub fn new() -> UnreadableMap {
+        UnreadableMap { regions: vec![] }
+    }
+
+    /// Remove all regions.
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    /// Add an unreadable range of bytes.
+    /// Ranges must be added in ascending order.
+    pub fn add(&mut self, offset: u64, len: u64) {
+        if let Some(last) = self.regions.last_mut() {
+            debug_assert!(offset >= last.end());
+            if offset == last.end() {
+                last.len += len;
+                return;
+            }
+        }
+        self.regions.push(UnreadableRegion { offset, len });
+    }
+
+    /// Returns true, if no unreadable sector has been found.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Get all unreadable regions in ascending order.
+    pub fn regions(&self) -> &[UnreadableRegion] {
+        &self.regions
+    }
+
+    /// Get the total number of unreadable sectors.
+    pub fn bad_sectors(&self, sector_size: u32) -> u64 {
+        count_sectors(
+            self.regions.iter().map(|r| (r.offset, r.end())),
+            sector_size,
+        )
+    }
+
+    /// Print the list of all unreadable sectors to the console.
+    pub fn print_report(&self, sector_size: u32) {
+        if self.is_empty() {
+            return;
+        }
+        println!("\nUnreadable sectors:");
+        for region in &self.regions {
+            let first = region.offset / sector_size as u64;
+            let last = (region.end() - 1) / sector_size as u64;
+            let sectors = if first == last {
+                format!("sector {}", first)
+            } else {
+                format!("sectors {} - {}", first, last)
+            };
+            println!(
+                "    {} - {}: {} ({})",
+                region.offset,
+                region.end() - 1,
+                sectors,
+                prettybytes(region.len, true, false, false),
+            );
+        }
+        println!(
+            "Total: {} unreadable sectors of {} bytes.\n",
+            self.bad_sectors(sector_size),
+            sector_size
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut map = MismatchMap::new();
+        assert!(map.is_empty());
+        map.add(10, 1, 0);
+        map.add(11, 1, 0);
+        map.add(11 + MISMATCH_MERGE_GAP, 1, 0);
+        map.add(1000, 1, 0);
+        map.add(2000, 1, 0);
+        map.add(2001, 1, 0);
+        let regions: Vec<(u64, u64, u64)> = map
+            .regions()
+            .iter()
+            .map(|r| (r.offset, r.len, r.bad_bytes))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![(10, MISMATCH_MERGE_GAP + 2, 3), (1000, 1, 1), (2000, 2, 2)]
+        );
+        assert_eq!(map.bad_bytes(), 6);
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.bad_bytes(), 0);
+        assert_eq!(map.bits().flipped(), 0);
+    }
+
+    #[test]
+    fn test_max_regions() {
+        let mut map = MismatchMap::new();
+        let count = MISMATCH_MAX_REGIONS as u64 * 3;
+        for i in 0..count {
+            map.add(i * MISMATCH_MERGE_GAP * 2, 0x01, 0x00);
+        }
+        assert!(map.regions().len() <= MISMATCH_MAX_REGIONS);
+        assert!(map.merge_gap() > MISMATCH_MERGE_GAP);
+        assert_eq!(map.bad_bytes(), count);
+        assert_eq!(
+            map.regions().iter().map(|r| r.bad_bytes).sum::<u64>(),
+            count
+        );
+        assert_eq!(
+            map.regions()
+                .iter()
+                .map(|r| r.bits.zero_to_one)
+                .sum::<u64>(),
+            count
+        );
+        assert_eq!(map.regions()[0].offset, 0);
+        assert_eq!(
+            map.regions().last().unwrap().end(),
+            (count - 1) * MISMATCH_MERGE_GAP * 2 + 1
+        );
+        map.clear();
+        assert_eq!(map.merge_gap(), MISMATCH_MERGE_GAP);
+    }
+
+    #[test]
+    fn test_buffers() {
+        let mut map = MismatchMap::new();
+        let expected = vec![0u8; 4096];
+        let mut actual = expected.clone();
+        actual[100] = 1;
+        actual[3000] = 2;
+        actual[3001] = 3;
+        assert_eq!(map.add_buffers(8192, &actual, &expected), 3);
+        assert_eq!(map.regions().len(), 2);
+        assert_eq!(map.regions()[0].offset, 8192 + 100);
+        assert_eq!(map.regions()[1].offset, 8192 + 3000);
+        assert_eq!(map.regions()[1].len, 2);
+    }
+
+    #[test]
+    fn test_sectors() {
+        let mut map = MismatchMap::new();
+        map.add(510, 1, 0);
+        map.add(513, 1, 0);
+        map.add(1000, 1, 0);
+        map.add(5000, 1, 0);
+        assert_eq!(map.regions()[0].sectors(512), (0, 1));
+        assert_eq!(map.regions()[1].sectors(512), (1, 1));
+        assert_eq!(map.regions()[2].sectors(512), (9, 9));
+        assert_eq!(map.bad_sectors(512), 3);
+        assert_eq!(map.bad_sectors(4096), 2);
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let mut map = UnreadableMap::new();
+        assert!(map.is_empty());
+        map.add(512, 512);
+        map.add(1024, 512);
+        map.add(4096, 512);
+        assert_eq!(
+            map.regions(),
+            &[
+                UnreadableRegion {
+                    offset: 512,
+                    len: 1024
+                },
+                UnreadableRegion {
+                    offset: 4096,
+                    len: 512
+                },
+            ]
+        );
+        assert_eq!(map.bad_sectors(512), 3);
+        assert_eq!(map.bad_sectors(4096), 2);
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut bits = MismatchBits::default();
+        bits.add(0b1000_0001, 0b0000_0011);
+        assert_eq!(bits.zero_to_one, 1);
+        assert_eq!(bits.one_to_zero, 1);
+        assert_eq!(bits.positions, [0, 1, 0, 0, 0, 0, 0, 1]);
+        bits.add(0xFF, 0x00);
+        assert_eq!(bits.zero_to_one, 9);
+        assert_eq!(bits.one_to_zero, 1);
+        assert_eq!(bits.flipped(), 10);
+        assert_eq!(bits.positions, [1, 2, 1, 1, 1, 1, 1, 2]);
+        assert_eq!(bits.positions_str(), "0,1,2,3,4,5,6,7");
+    }
+
+    #[test]
+    fn test_kind() {
+        let expected = vec![0x0Fu8; 1024];
+
+        // Single bit flips.
+        let mut map = MismatchMap::new();
+        let mut actual = expected.clone();
+        actual[10] = 0x1F;
+        actual[20] = 0x0E;
+        map.add_buffers(0, &actual, &expected);
+        assert_eq!(map.regions()[0].kind(512), MismatchKind::ScatteredBits);
+        assert_eq!(map.bits().positions_str(), "0,4");
+
+        // A single flip is not a stuck bit.
+        let mut map = MismatchMap::new();
+        let mut actual = expected.clone();
+        actual[10] = 0x0E;
+        map.add_buffers(0, &actual, &expected);
+        assert_eq!(map.regions()[0].kind(512), MismatchKind::ScatteredBits);
+
+        // Stuck-at.
+        let mut map = MismatchMap::new();
+        let actual: Vec<u8> = expected.iter().map(|x| x & 0xFE).collect();
+        map.add_buffers(0, &actual, &expected);
+        assert_eq!(map.regions()[0].kind(512), MismatchKind::StuckAtZero);
+        let mut map = MismatchMap::new();
+        let actual: Vec<u8> = expected.iter().map(|x| x | 0x10).collect();
+        map.add_buffers(0, &actual, &expected);
+        assert_eq!(map.regions()[0].kind(512), MismatchKind::StuckAtOne);
+
+        // Whole sector replaced.
+        let mut map = MismatchMap::new();
+        let actual: Vec<u8> = expected.iter().map(|x| !x).collect();
+        map.add_buffers(0, &actual, &expected);
+        assert_eq!(map.regions()[0].kind(512), MismatchKind::WholeSectors);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -36,6 +36,7 @@ trait RawIoOsIntf {
     fn drop_file_caches(&mut self, offset: u64, size: u64) -> ah::Result<()>;
     fn close(&mut self) -> ah::Result<()>;
     fn sync(&mut self) -> ah::Result<()>;
+    #[cfg(test)]
     fn set_len(&mut self, size: u64) -> ah::Result<()>;
     fn seek(&mut self, offset: u64) -> ah::Result<u64>;
     fn read(&mut self, buffer: &mut [u8]) -> ah::Result<RawIoResult>;
@@ -91,7 +92,7 @@ impl RawIo {
         self.os.sync()
     }
 
-    #[cfg_attr(not(test), allow(dead_code))]
+    #[cfg(test)]
     pub fn set_len(&mut self, size: u64) -> ah::Result<()> {
         self.os.set_len(size)
     }
@@ -112,4 +113,73 @@ impl RawIo {
     }
 }
 
+/// Raw I/O wrapper that fails all reads touching a bad byte range.
+#[cfg(test)]
+struct RawIoBadRanges {
+    inner: Box<dyn RawIoOsIntf>,
+    bad: Vec<std::ops::Range<u64>>,
+    pos: u64,
+}
+
+#[cfg(test)]
+impl RawIoOsIntf for RawIoBadRanges {
+    fn get_sector_size(&self) -> Option<u32> {
+        self.inner.get_sector_size()
+    }
+
+    fn drop_file_caches(&mut self, offset: u64, size: u64) -> ah::Result<()> {
+        self.inner.drop_file_caches(offset, size)
+    }
+
+    fn close(&mut self) -> ah::Result<()> {
+        self.inner.close()
+    }
+
+    fn sync(&mut self) -> ah::Result<()> {
+        self.inner.sync()
+    }
+
+    fn set_len(&mut self, size: u64) -> ah::Result<()> {
+        self.inner.set_len(size)
+    }
+
+    fn seek(&mut self, offset: u64) -> ah::Result<u64> {
+        self.pos = self.inner.seek(offset)?;
+        Ok(self.pos)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> ah::Result<RawIoResult> {
+        let end = self.pos + buffer.len() as u64;
+        if self.bad.iter().any(|r| r.start < end && self.pos < r.end) {
+            return Err(
+                ah::Error::new(std::io::Error::from_raw_os_error(libc::EIO)).context("Read error")
+            );
+        }
+        let res = self.inner.read(buffer)?;
+        if let RawIoResult::Ok(n) = res {
+            self.pos += n as u64;
+        }
+        Ok(res)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> ah::Result<RawIoResult> {
+        let res = self.inner.write(buffer)?;
+        if let RawIoResult::Ok(n) = res {
+            self.pos += n as u64;
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+impl RawIo {
+    /// Open a file that fails all reads touching one of the bad byte ranges.
+    pub fn new_bad_ranges(path: &Path, bad: Vec<std::ops::Range<u64>>) -> ah::Result<Self> {
+        let inner = RawIo::new(path, false, true, false)?.os;
+        Ok(Self {
+            os: Box::new(RawIoBadRanges { inner, bad, pos: 0 }),
+        })
+    }
+}
+
 // vim: ts=4 sw=4 expandtab
@@ -198,6 +198,7 @@ impl RawIoOsIntf for RawIoLinux {
         Ok(())
     }
 
+    #[cfg(test)]
     fn set_len(&mut self, size: u64) -> ah::Result<()> {
         if !self.write_mode {
             return Err(ah::format_err!("File is opened without write permission."));
@@ -227,7 +228,7 @@ impl RawIoOsIntf for RawIoLinux {
         };
         match file.read(buffer) {
             Ok(count) => Ok(RawIoResult::Ok(count)),
-            Err(e) => Err(ah::format_err!("Read error: {}", e)),
+            Err(e) => Err(ah::Error::new(e).context("Read error")),
         }
     }
 
@@ -244,7 +245,7 @@ impl RawIoOsIntf for RawIoLinux {
                     return Ok(RawIoResult::Enospc);
                 }
             }
-            return Err(ah::format_err!("Write error: {}", e));
+            return Err(ah::Error::new(e).context("Write error"));
         }
         Ok(RawIoResult::Ok(buffer.len()))
     }
@@ -360,6 +360,7 @@ impl RawIoOsIntf for RawIoWindows {
         }
     }
 
+    #[cfg(test)]
     fn set_len(&mut self, size: u64) -> ah::Result<()> {
         if !self.write_mode {
             return Err(ah::format_err!("File is opened without write permission."));
@@ -0,0 +1,384 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crate::displacement::Displacement;
+use crate::error::{exit_code, DisktestError};
+use crate::mismatch::{MismatchBits, MismatchRegion, UnreadableRegion};
+use crate::stream_aggregator::DtStreamType;
+use anyhow as ah;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Version of the JSON report format.
+/// This is incremented on incompatible changes only.
+/// New fields may be added without incrementing the version.
+pub const REPORT_FORMAT_VERSION: u32 = 1;
+
+/// Length of a throughput measurement window.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Minimum and maximum throughput measurement.
+#[derive(Clone, Debug)]
+pub struct Throughput {
+    window_begin: Instant,
+    window_bytes: u64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Default for Throughput {
+    fn default() -> Throughput {
+        Throughput::new()
+    }
+}
+
+impl Throughput {
+    pub fn new() -> Throughput {
+        Throughput {
+            window_begin: Instant::now(),
+            window_bytes: 0,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Account processed bytes.
+    pub fn add(&mut self, bytes: u64) {
+        self.add_at(bytes, Instant::now());
+    }
+
+    fn add_at(&mut self, bytes: u64, now: Instant) {
+        self.window_bytes += bytes;
+        let elapsed = now.duration_since(self.window_begin);
+        if elapsed >= THROUGHPUT_WINDOW {
+            let rate = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.min = Some(self.min.map_or(rate, |min| min.min(rate)));
+            self.max = Some(self.max.map_or(rate, |max| max.max(rate)));
+            self.window_begin = now;
+            self.window_bytes = 0;
+        }
+    }
+
+    /// Minimum throughput in bytes per second.
+    /// None, if no measurement window has completed.
+    pub fn min(&self) -> Option<f64> {
+        self.min
+    }
+
+    /// Maximum throughput in bytes per second.
+    /// None, if no measurement window has completed.
+    pub fn max(&self) -> Option<f64> {
+        self.max
+    }
+}
+
+/// Report of one write or verify phase.
+#[derive(Clone, Debug)]
+pub struct PhaseReport {
+    /// Name of the phase ("write" or "verify").
+    pub phase: &'static str,
+    /// The pass number, starting at 1.
+    pub pass: u32,
+    /// Absolute byte offset the phase started at.
+    pub offset: u64,
+    /// Number of bytes processed.
+    pub bytes: u64,
+    pub duration: Duration,
+    pub throughput: Throughput,
+    pub sector_size: u32,
+    pub threads: usize,
+    /// Error that terminated the phase and its message.
+    pub error: Option<(Option<DisktestError>, String)>,
+    pub mismatches: Vec<MismatchRegion>,
+    /// The gap, below which corrupted bytes have been merged into one region.
+    pub merge_gap: u64,
+    pub bits: MismatchBits,
+    pub unreadable: Vec<UnreadableRegion>,
+    pub displacements: Vec<Displacement>,
+}
+
+/// Get the machine readable name of an error.
+fn error_kind(error: &Option<DisktestError>) -> &'static str {
+    error.as_ref().map_or("error", |e| e.name())
+}
+
+/// Convert a terminating error to JSON.
+fn error_json(error: &Option<DisktestError>, message: &str) -> Value {
+    let mut value = json!({
+        "kind": error_kind(error),
+        "message": message,
+    });
+    let fields = match error {
+        Some(DisktestError::Mismatch { offset, bad_bytes }) => {
+            json!({"offset": offset, "bad_bytes": bad_bytes})
+        }
+        Some(DisktestError::ReadError { offset, errno })
+        | Some(DisktestError::WriteError { offset, errno }) => {
+            json!({"offset": offset, "errno": errno})
+        }
+        Some(DisktestError::OutOfSpace { offset }) => json!({ "offset": offset }),
+        _ => json!({}),
+    };
+    if let (Some(value), Value::Object(fields)) = (value.as_object_mut(), fields) {
+        value.extend(fields);
+    }
+    value
+}
+
+impl PhaseReport {
+    /// Get the machine readable result of this phase.
+    pub fn result(&self) -> &'static str {
+        match &self.error {
+            None => "success",
+            Some((error, _)) => error_kind(error),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let secs = self.duration.as_secs_f64();
+        let average = if secs > 0.0 {
+            Some(self.bytes as f64 / secs)
+        } else {
+            None
+        };
+
+        let mut errors = vec![];
+        if let Some((error, message)) = &self.error {
+            errors.push(error_json(error, message));
+        }
+        for region in &self.mismatches {
+            errors.push(json!({
+                "kind": "corrupted_region",
+                "offset": region.offset,
+                "len": region.len,
+                "bad_bytes": region.bad_bytes,
+                "flipped_bits": region.bits.flipped(),
+                "class": region.kind(self.sector_size).to_string(),
+            }));
+        }
+        for region in &self.unreadable {
+            errors.push(json!({
+                "kind": "unreadable_region",
+                "offset": region.offset,
+                "len": region.len,
+            }));
+        }
+
+        let displacements: Vec<Value> = self
+            .displacements
+            .iter()
+            .map(|d| {
+                json!({
+                    "offset": d.offset,
+                    "description": d.to_string(),
+                })
+            })
+            .collect();
+
+        json!({
+            "phase": self.phase,
+            "pass": self.pass,
+            "offset": self.offset,
+            "bytes": self.bytes,
+            "duration": secs,
+            "throughput": {
+                "average": average,
+                "min": self.throughput.min(),
+                "max": self.throughput.max(),
+            },
+            "result": self.result(),
+            "errors": errors,
+            "mismatch_merge_gap": self.merge_gap,
+            "flipped_bits": {
+                "total": self.bits.flipped(),
+                "zero_to_one": self.bits.zero_to_one,
+                "one_to_zero": self.bits.one_to_zero,
+            },
+            "displacements": displacements,
+        })
+    }
+}
+
+/// Machine readable report of a disktest run.
+pub struct Report {
+    device: PathBuf,
+    algorithm: DtStreamType,
+    threads: usize,
+    sector_size: Option<u32>,
+    seed_fingerprint: String,
+    stream_fingerprint: String,
+    phases: Vec<PhaseReport>,
+}
+
+impl Report {
+    pub fn new(
+        device: &Path,
+        algorithm: DtStreamType,
+        threads: usize,
+        seed_fingerprint: String,
+        stream_fingerprint: String,
+    ) -> Report {
+        Report {
+            device: device.to_path_buf(),
+            algorithm,
+            threads,
+            sector_size: None,
+            seed_fingerprint,
+            stream_fingerprint,
+            phases: vec![],
+        }
+    }
+
+    /// Add the report of a finished phase.
+    pub fn add_phase(&mut self, phase: PhaseReport) {
+        self.threads = phase.threads;
+        self.sector_size = Some(phase.sector_size);
+        self.phases.push(phase);
+    }
+
+    pub fn to_json(&self, result: &ah::Result<()>) -> Value {
+        let (result_name, code) = match result {
+            Ok(_) => ("success", 0),
+            Err(e) => (
+                e.downcast_ref::<DisktestError>()
+                    .map_or("error", |e| e.name()),
+                exit_code(e),
+            ),
+        };
+        json!({
+            "format_version": REPORT_FORMAT_VERSION,
+            "disktest_version": env!("CARGO_PKG_VERSION"),
+            "device": self.device.to_string_lossy(),
+            "sector_size": self.sector_size,
+            "algorithm": self.algorithm.to_string(),
+            "threads": self.threads,
+            "seed_fingerprint": self.seed_fingerprint,
+            "stream_fingerprint": self.stream_fingerprint,
+            "phases": self.phases.iter().map(|p| p.to_json()).collect::<Vec<_>>(),
+            "result": result_name,
+            "exit_code": code,
+        })
+    }
+
+    /// Write the report to a JSON file.
+    pub fn write(&self, path: &Path, result: &ah::Result<()>) -> ah::Result<()> {
+        let mut text = serde_json::to_string_pretty(&self.to_json(result))?;
+        text.push('\n');
+        std::fs::write(path, text)
+            .map_err(|e| ah::format_err!("Failed to write report {:?}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mismatch::MISMATCH_MERGE_GAP;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_throughput() {
+        let mut tp = Throughput::new();
+        let begin = tp.window_begin;
+        tp.add_at(1000, begin + Duration::from_millis(500));
+        assert_eq!(tp.min(), None);
+        tp.add_at(1000, begin + Duration::from_secs(1));
+        assert_eq!(tp.min(), Some(2000.0));
+        tp.add_at(500, begin + Duration::from_secs(2));
+        assert_eq!(tp.min(), Some(500.0));
+        assert_eq!(tp.max(), Some(2000.0));
+    }
+
+    #[test]
+    fn test_report() {
+        let phase = PhaseReport {
+            phase: "verify",
+            pass: 1,
+            offset: 0,
+            bytes: 4096,
+            duration: Duration::from_secs(2),
+            throughput: Throughput::new(),
+            sector_size: 512,
+            threads: 2,
+            error: Some((
+                Some(DisktestError::Mismatch {
+                    offset: 10,
+                    bad_bytes: 1,
+                }),
+                "Data MISMATCH at byte 10!".to_string(),
+            )),
+            mismatches: vec![MismatchRegion {
+                offset: 10,
+                len: 1,
+                bad_bytes: 1,
+                bits: MismatchBits::default(),
+            }],
+            merge_gap: MISMATCH_MERGE_GAP,
+            bits: MismatchBits::default(),
+            unreadable: vec![],
+            displacements: vec![],
+        };
+        let mut report = Report::new(
+            Path::new("/dev/foo"),
+            DtStreamType::Crc,
+            0,
+            "0123".to_string(),
+            "4567".to_string(),
+        );
+        report.add_phase(phase);
+        let result = Err(DisktestError::Mismatch {
+            offset: 10,
+            bad_bytes: 1,
+        }
+        .msg("Data MISMATCH at byte 10!"));
+
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("report.json");
+        report.write(&path, &result).unwrap();
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["format_version"], 1);
+        assert_eq!(json["device"], "/dev/foo");
+        assert_eq!(json["algorithm"], "CRC");
+        assert_eq!(json["threads"], 2);
+        assert_eq!(json["sector_size"], 512);
+        assert_eq!(json["stream_fingerprint"], "4567");
+        assert_eq!(json["result"], "mismatch");
+        assert_eq!(json["exit_code"], 3);
+        let phase = &json["phases"][0];
+        assert_eq!(phase["phase"], "verify");
+        assert_eq!(phase["pass"], 1);
+        assert_eq!(phase["result"], "mismatch");
+        assert_eq!(phase["throughput"]["average"], 2048.0);
+        // No measurement window has completed.
+        assert!(phase["throughput"]["min"].is_null());
+        assert!(phase["throughput"]["max"].is_null());
+        assert_eq!(phase["errors"][0]["kind"], "mismatch");
+        assert_eq!(phase["errors"][0]["offset"], 10);
+        assert_eq!(phase["errors"][0]["bad_bytes"], 1);
+        assert_eq!(phase["errors"][0]["message"], "Data MISMATCH at byte 10!");
+        assert_eq!(phase["errors"][1]["kind"], "corrupted_region");
+        assert_eq!(phase["errors"][1]["bad_bytes"], 1);
+        assert_eq!(phase["mismatch_merge_gap"], MISMATCH_MERGE_GAP);
+        tdir.close().unwrap();
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -0,0 +1,267 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Overwrite the buffer with zeros.
+/// The writes are not optimized away, even if the buffer is never read again.
+pub fn wipe(buf: &mut [u8]) {
+    for x in buf.iter_mut() {
+        // SAFETY: x is a valid and aligned reference.
+        unsafe { std::ptr::write_volatile(x, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Overwrite a value, that holds secret material, with a harmless value.
+/// The writes are not optimized away, even if the value is never read again.
+/// The old value is not dropped. So it must not own heap memory.
+pub fn wipe_value<T>(dst: &mut T, value: T) {
+    // SAFETY: dst is a valid and aligned reference.
+    unsafe { std::ptr::write_volatile(dst, value) };
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Get the size of a memory page.
+#[cfg(not(target_os = "windows"))]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions.
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as usize
+    } else {
+        4096
+    }
+}
+
+/// Get the size of a memory page.
+#[cfg(target_os = "windows")]
+fn page_size() -> usize {
+    4096
+}
+
+/// Lock the memory into RAM and exclude it from core dumps.
+/// Returns false, if the memory could not be locked.
+#[cfg(not(target_os = "windows"))]
+fn lock(ptr: *mut u8, size: usize) -> bool {
+    #[cfg(target_os = "linux")]
+    // SAFETY: The memory range is a page aligned allocation.
+    unsafe {
+        libc::madvise(ptr as *mut libc::c_void, size, libc::MADV_DONTDUMP);
+    }
+    // SAFETY: The memory range is a valid allocation.
+    unsafe { libc::mlock(ptr as *const libc::c_void, size) == 0 }
+}
+
+/// Unlock the memory.
+#[cfg(not(target_os = "windows"))]
+fn unlock(ptr: *mut u8, size: usize) {
+    // SAFETY: The memory range is a valid allocation.
+    unsafe { libc::munlock(ptr as *const libc::c_void, size) };
+}
+
+/// Lock the memory into RAM.
+/// Returns false, if the memory could not be locked.
+#[cfg(target_os = "windows")]
+fn lock(ptr: *mut u8, size: usize) -> bool {
+    // SAFETY: The memory range is a valid allocation.
+    unsafe { winapi::um::memoryapi::VirtualLock(ptr as _, size) != 0 }
+}
+
+/// Unlock the memory.
+#[cfg(target_os = "windows")]
+fn unlock(ptr: *mut u8, size: usize) {
+    // SAFETY: The memory range is a valid allocation.
+    unsafe { winapi::um::memoryapi::VirtualUnlock(ptr as _, size) };
+}
+
+/// Buffer for secret material, like seeds and derived keys.
+/// The memory is locked into RAM, if possible, so that it is never swapped out.
+/// It is overwritten with zeros before it is freed.
+/// Every buffer occupies whole memory pages of its own,
+/// so that unlocking it does not unlock another buffer.
+pub struct DtSecret {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    locked: bool,
+}
+
+// SAFETY: DtSecret exclusively owns its memory, like a Box<[u8]>.
+unsafe impl Send for DtSecret {}
+// SAFETY: DtSecret exclusively owns its memory, like a Box<[u8]>.
+unsafe impl Sync for DtSecret {}
+
+impl DtSecret {
+    /// Create a new buffer of len zero bytes.
+    pub fn new(len: usize) -> DtSecret {
+        let page_size = page_size();
+        let size = len.max(1).div_ceil(page_size) * page_size;
+        let layout = Layout::from_size_align(size, page_size).expect("Invalid secret size");
+        // SAFETY: The layout has a non-zero size.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            handle_alloc_error(layout);
+        };
+        let locked = lock(ptr.as_ptr(), size);
+        DtSecret {
+            ptr,
+            len,
+            layout,
+            locked,
+        }
+    }
+
+    /// Create a new buffer with a copy of the data.
+    pub fn from_slice(data: &[u8]) -> DtSecret {
+        let mut secret = DtSecret::new(data.len());
+        secret.copy_from_slice(data);
+        secret
+    }
+
+    /// Check whether the memory is locked into RAM.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Get the secret as a string.
+    /// Panics, if the secret is not valid UTF-8.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(self).expect("The secret is not valid UTF-8.")
+    }
+}
+
+impl Drop for DtSecret {
+    fn drop(&mut self) {
+        // SAFETY: The allocation is valid for layout.size() bytes.
+        let buf = unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) };
+        wipe(buf);
+        if self.locked {
+            unlock(self.ptr.as_ptr(), self.layout.size());
+        }
+        // SAFETY: The memory was allocated with this layout.
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+impl Deref for DtSecret {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: The allocation is valid for at least len bytes.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for DtSecret {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The allocation is valid for at least len bytes.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Clone for DtSecret {
+    fn clone(&self) -> DtSecret {
+        DtSecret::from_slice(self)
+    }
+}
+
+impl PartialEq for DtSecret {
+    fn eq(&self, other: &DtSecret) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for DtSecret {}
+
+impl std::fmt::Debug for DtSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DtSecret({} bytes)", self.len)
+    }
+}
+
+impl From<&[u8]> for DtSecret {
+    fn from(data: &[u8]) -> DtSecret {
+        DtSecret::from_slice(data)
+    }
+}
+
+impl From<&str> for DtSecret {
+    fn from(data: &str) -> DtSecret {
+        DtSecret::from_slice(data.as_bytes())
+    }
+}
+
+/// The vector is wiped after it has been copied.
+impl From<Vec<u8>> for DtSecret {
+    fn from(mut data: Vec<u8>) -> DtSecret {
+        let secret = DtSecret::from_slice(&data);
+        // Also wipe the unused capacity.
+        data.resize(data.capacity(), 0);
+        wipe(&mut data);
+        secret
+    }
+}
+
+/// The string is wiped after it has been copied.
+impl From<String> for DtSecret {
+    fn from(data: String) -> DtSecret {
+        DtSecret::from(data.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret() {
+        let a = DtSecret::from("foo");
+        assert_eq!(&*a, b"foo");
+        assert_eq!(a.as_str(), "foo");
+        assert_eq!(a.clone(), a);
+        assert_ne!(DtSecret::from(vec![1, 2, 3]), a);
+        assert_eq!(format!("{:?}", a), "DtSecret(3 bytes)");
+        assert_eq!(a.ptr.as_ptr() as usize % page_size(), 0);
+
+        let mut b = DtSecret::new(page_size() + 1);
+        assert_eq!(b.len(), page_size() + 1);
+        assert!(b.iter().all(|&x| x == 0));
+        b[page_size()] = 42;
+        assert_eq!(b.layout.size(), page_size() * 2);
+        assert!(DtSecret::new(0).is_empty());
+    }
+
+    #[test]
+    fn test_wipe() {
+        let mut buf = [1u8, 2, 3];
+        wipe(&mut buf);
+        assert_eq!(buf, [0, 0, 0]);
+        let mut value = 42u64;
+        wipe_value(&mut value, 0);
+        assert_eq!(value, 0);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -19,48 +19,339 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+use anyhow as ah;
+use disktest::DtSecret;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use std::env::VarError;
+use std::fs::{File, OpenOptions};
+use std::io::{stdin, ErrorKind, Read, Write};
+use std::path::Path;
+
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::OpenOptionsExt;
+
+#[cfg(not(target_os = "windows"))]
+use std::os::fd::AsFd;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::io::AsHandle;
+
+/// Maximum length of a seed, that is read from a file or stdin, in bytes.
+pub const MAX_SEED_LEN: usize = 1024;
 
 /// Generate a new alphanumeric truly random seed.
 /// length: The number of ASCII characters to return.
-pub fn gen_seed_string(length: usize) -> String {
-    let rng = thread_rng();
-    rng.sample_iter(Alphanumeric)
-        .take(length)
-        .map(char::from)
-        .collect()
+pub fn gen_seed_string(length: usize) -> DtSecret {
+    let mut rng = thread_rng();
+    let mut seed = DtSecret::new(length);
+    for c in seed.iter_mut() {
+        *c = rng.sample(Alphanumeric);
+    }
+    seed
+}
+
+/// Get the seed of a pass of a multi-pass run.
+/// Every two passes (normal and inverted pattern) share one seed.
+/// The first two passes use the seed itself.
+/// pass: The pass number, starting at 1.
+pub fn pass_seed(seed: &str, pass: u32) -> DtSecret {
+    let group = pass.saturating_sub(1) / 2;
+    if group == 0 {
+        return DtSecret::from(seed);
+    }
+    // Copy the seed directly into the secret buffer,
+    // so that no unwiped copies are left behind on the heap.
+    // The suffix is not secret.
+    let suffix = format!("#pass{}", group + 1);
+    let mut pass_seed = DtSecret::new(seed.len() + suffix.len());
+    pass_seed[..seed.len()].copy_from_slice(seed.as_bytes());
+    pass_seed[seed.len()..].copy_from_slice(suffix.as_bytes());
+    pass_seed
 }
 
-/// Print the generated seed to the console.
-pub fn print_generated_seed(seed: &str, verbose: bool) {
+/// Print the generated seed and the stream fingerprint to the console.
+pub fn print_generated_seed(seed: &str, fingerprint: &str, verbose: bool) {
     if verbose {
         println!(
-            "\nThe generated --seed is:\n    {}\nUse this seed for subsequent --verify.\n",
-            seed
+            "\nThe generated --seed is:\n    {}\n\
+             Use this seed for subsequent --verify.\n\
+             The stream fingerprint is {}.\n",
+            seed, fingerprint
         );
     } else {
-        println!("Generated --seed {}\n", seed);
+        println!(
+            "Generated --seed {} (stream fingerprint {})\n",
+            seed, fingerprint
+        );
     }
 }
 
+/// Read the seed from the first line of the reader.
+/// The reader must not be buffered, because its buffer would keep unwiped copies of the seed.
+/// The line is read byte by byte directly into a locked buffer,
+/// so that nothing after the first line is consumed.
+/// source: Human readable name of the source, for error messages.
+pub fn read_seed(mut reader: impl Read, source: &str) -> ah::Result<DtSecret> {
+    // Room for a CR and one more byte than allowed, to detect oversized seeds.
+    let mut buf = DtSecret::new(MAX_SEED_LEN + 2);
+    let mut len = 0;
+    let mut newline = false;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..len + 1]) {
+            Ok(0) => break,
+            Ok(_) if buf[len] == b'\n' => {
+                buf[len] = 0;
+                newline = true;
+                break;
+            }
+            Ok(_) => len += 1,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => {
+                return Err(ah::format_err!(
+                    "Failed to read the seed from {}: {}",
+                    source,
+                    e
+                ))
+            }
+        }
+    }
+    if newline && len > 0 && buf[len - 1] == b'\r' {
+        len -= 1;
+    }
+    if len > MAX_SEED_LEN {
+        return Err(ah::format_err!(
+            "The seed from {} is longer than {} bytes.",
+            source,
+            MAX_SEED_LEN
+        ));
+    }
+    if len == 0 {
+        return Err(ah::format_err!("The seed from {} is empty.", source));
+    }
+    if std::str::from_utf8(&buf[..len]).is_err() {
+        return Err(ah::format_err!(
+            "The seed from {} is not valid UTF-8.",
+            source
+        ));
+    }
+    Ok(DtSecret::from_slice(&buf[..len]))
+}
+
+/// Read the seed from the first line of a file.
+pub fn read_seed_file(path: &Path) -> ah::Result<DtSecret> {
+    let file = File::open(path)
+        .map_err(|e| ah::format_err!("Failed to open the seed file {:?}: {}", path, e))?;
+    read_seed(file, &format!("the file {:?}", path))
+}
+
+/// Open a duplicate of the stdin handle.
+/// Reading from it bypasses the buffer of `std::io::Stdin`, that is never wiped.
+#[cfg(not(target_os = "windows"))]
+fn stdin_unbuffered() -> std::io::Result<File> {
+    Ok(File::from(stdin().as_fd().try_clone_to_owned()?))
+}
+
+/// Open a duplicate of the stdin handle.
+/// Reading from it bypasses the buffer of `std::io::Stdin`, that is never wiped.
+#[cfg(target_os = "windows")]
+fn stdin_unbuffered() -> std::io::Result<File> {
+    Ok(File::from(stdin().as_handle().try_clone_to_owned()?))
+}
+
+/// Read the seed from the first line of stdin.
+pub fn read_seed_stdin() -> ah::Result<DtSecret> {
+    let file = stdin_unbuffered()
+        .map_err(|e| ah::format_err!("Failed to read the seed from stdin: {}", e))?;
+    read_seed(file, "stdin")
+}
+
+/// Read the seed from an environment variable.
+pub fn read_seed_env(name: &str) -> ah::Result<DtSecret> {
+    seed_from_env(name, std::env::var(name))
+}
+
+/// Get the seed from the value of the environment variable.
+fn seed_from_env(name: &str, value: Result<String, VarError>) -> ah::Result<DtSecret> {
+    let seed = value.map(DtSecret::from).map_err(|e| {
+        ah::format_err!(
+            "Failed to read the seed from the environment variable {}: {}",
+            name,
+            e
+        )
+    })?;
+    if seed.is_empty() {
+        return Err(ah::format_err!(
+            "The seed from the environment variable {} is empty.",
+            name
+        ));
+    }
+    Ok(seed)
+}
+
+/// Run a function with the terminal echo of stdin turned off.
+#[cfg(not(target_os = "windows"))]
+fn with_echo_off<T>(f: impl FnOnce() -> ah::Result<T>) -> ah::Result<T> {
+    // SAFETY: termios is plain data and it is initialized by tcgetattr.
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: term is a valid termios struct.
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) } != 0 {
+        return Err(ah::format_err!(
+            "The seed prompt requires a terminal on stdin."
+        ));
+    }
+    let mut noecho = term;
+    noecho.c_lflag &= !libc::ECHO;
+    // SAFETY: noecho is a valid termios struct.
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &noecho) } != 0 {
+        return Err(ah::format_err!("Failed to turn off the terminal echo."));
+    }
+    let res = f();
+    // SAFETY: term is the valid original termios struct.
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+    res
+}
+
+/// Run a function with the console echo of stdin turned off.
+#[cfg(target_os = "windows")]
+fn with_echo_off<T>(f: impl FnOnce() -> ah::Result<T>) -> ah::Result<T> {
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_INPUT_HANDLE;
+    use winapi::um::wincon::ENABLE_ECHO_INPUT;
+
+    // SAFETY: GetStdHandle has no preconditions.
+    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    let mut mode = 0;
+    // SAFETY: mode is a valid pointer.
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        return Err(ah::format_err!(
+            "The seed prompt requires a console on stdin."
+        ));
+    }
+    // SAFETY: handle is a console handle.
+    if unsafe { SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT) } == 0 {
+        return Err(ah::format_err!("Failed to turn off the console echo."));
+    }
+    let res = f();
+    // SAFETY: handle is a console handle and mode is its original mode.
+    unsafe { SetConsoleMode(handle, mode) };
+    res
+}
+
+/// Interactively prompt for the seed without echoing it to the terminal.
+pub fn prompt_seed() -> ah::Result<DtSecret> {
+    eprint!("Seed: ");
+    std::io::stderr().flush()?;
+    let res = with_echo_off(read_seed_stdin);
+    eprintln!();
+    res
+}
+
+/// Write the generated seed to a new file, that only the owner can read.
+/// An existing file is not overwritten.
+pub fn save_generated_seed(path: &Path, seed: &str) -> ah::Result<()> {
+    let mut opts = OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(not(target_os = "windows"))]
+    opts.mode(0o600);
+    let mut file = opts
+        .open(path)
+        .map_err(|e| ah::format_err!("Failed to create the seed file {:?}: {}", path, e))?;
+    writeln!(file, "{}", seed)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| ah::format_err!("Failed to write the seed file {:?}: {}", path, e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::tempdir;
 
     #[test]
     fn test_gen() {
         // Check returned ASCII string length.
         let seed = gen_seed_string(42);
         assert_eq!(seed.len(), 42);
-        assert_eq!(seed.chars().count(), 42);
+        assert_eq!(seed.as_str().chars().count(), 42);
+        assert!(seed.iter().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_pass_seed() {
+        assert_eq!(pass_seed("foo", 1).as_str(), "foo");
+        assert_eq!(pass_seed("foo", 2).as_str(), "foo");
+        assert_eq!(pass_seed("foo", 3).as_str(), "foo#pass2");
+        assert_eq!(pass_seed("foo", 4).as_str(), "foo#pass2");
+        assert_eq!(pass_seed("foo", 5).as_str(), "foo#pass3");
+    }
+
+    #[test]
+    fn test_read() {
+        let read = |s: &str| {
+            read_seed(s.as_bytes(), "test")
+                .ok()
+                .map(|seed| seed.as_str().to_string())
+        };
+        assert_eq!(read("foo"), Some("foo".to_string()));
+        assert_eq!(read("foo bar\n"), Some("foo bar".to_string()));
+        assert_eq!(read("foo\r\nbar\n"), Some("foo".to_string()));
+        assert_eq!(read("\nfoo\n"), None);
+        assert_eq!(read("\r\n"), None);
+        assert_eq!(read(""), None);
+        assert_eq!(read("\u{e4}\n"), Some("\u{e4}".to_string()));
+        assert!(read_seed(&[0xFF, b'\n'][..], "test").is_err());
+
+        // Only the first line is consumed.
+        let mut reader = "foo\nbar\n".as_bytes();
+        assert_eq!(read_seed(&mut reader, "test").unwrap().as_str(), "foo");
+        assert_eq!(reader, b"bar\n");
+
+        // Oversized seeds are rejected.
+        let max = "x".repeat(MAX_SEED_LEN);
+        assert_eq!(read(&format!("{}\n", max)), Some(max.clone()));
+        assert_eq!(read(&format!("{}\r\n", max)), Some(max.clone()));
+        assert_eq!(read(&max), Some(max.clone()));
+        assert_eq!(read(&format!("{}x", max)), None);
+        assert_eq!(read(&format!("{}x\n", max)), None);
+        assert_eq!(read(&format!("{}\rx\n", max)), None);
+
+        // Don't modify the environment, that is shared by all tests.
+        assert_eq!(
+            seed_from_env("FOO", Ok("envseed".to_string()))
+                .unwrap()
+                .as_str(),
+            "envseed"
+        );
+        assert!(seed_from_env("FOO", Ok("".to_string())).is_err());
+        assert!(seed_from_env("FOO", Err(VarError::NotPresent)).is_err());
+        assert!(read_seed_env("DISKTEST_TEST_SEED_ENV_UNSET").is_err());
+    }
+
+    #[test]
+    fn test_save() {
+        let tdir = tempdir().unwrap();
+        let path = tdir.path().join("seed");
+        save_generated_seed(&path, "foo").unwrap();
+        assert_eq!(read_seed_file(&path).unwrap().as_str(), "foo");
+        #[cfg(not(target_os = "windows"))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // An existing file is not overwritten.
+        assert!(save_generated_seed(&path, "bar").is_err());
+        assert_eq!(read_seed_file(&path).unwrap().as_str(), "foo");
+        tdir.close().unwrap();
     }
 
     #[test]
     fn test_print() {
         // Just check if it doesn't panic.
-        print_generated_seed("foo", false);
-        print_generated_seed("bar", true);
+        print_generated_seed("foo", "0123", false);
+        print_generated_seed("bar", "4567", true);
     }
 }
 
@@ -20,10 +20,14 @@
 //
 
 use crate::bufcache::{BufCache, BufCacheCons};
+use crate::dedup::DedupMap;
+use crate::error::DisktestError;
 use crate::generator::{
-    GeneratorChaCha12, GeneratorChaCha20, GeneratorChaCha8, GeneratorCrc, NextRandom,
+    find_generator, GeneratorCompress, GeneratorFactory, GeneratorParams, GeneratorPattern,
+    GeneratorTagged, PatternFactory, RandomFactory, TaggedFactory,
 };
-use crate::kdf::kdf;
+use crate::kdf::DtKeySet;
+use crate::secret::DtSecret;
 use anyhow as ah;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -32,6 +36,73 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
+/// User supplied repeating byte pattern.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DtPattern {
+    bytes: [u8; DtPattern::MAX_LEN],
+    len: usize,
+}
+
+impl DtPattern {
+    /// Maximum length of a user supplied pattern, in bytes.
+    pub const MAX_LEN: usize = 32;
+
+    pub fn new(pattern: &[u8]) -> ah::Result<DtPattern> {
+        if pattern.is_empty() || pattern.len() > DtPattern::MAX_LEN {
+            return Err(ah::format_err!(
+                "The pattern must be 1 to {} bytes long.",
+                DtPattern::MAX_LEN
+            ));
+        }
+        let mut bytes = [0; DtPattern::MAX_LEN];
+        bytes[..pattern.len()].copy_from_slice(pattern);
+        Ok(DtPattern {
+            bytes,
+            len: pattern.len(),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Parameters of the tagged stream, that are written to every block tag.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DtTagged {
+    pub run_id: u64,
+    pub pass: u32,
+}
+
+/// Generator registered with register_generator().
+#[derive(Copy, Clone)]
+pub struct DtCustom(&'static dyn GeneratorFactory);
+
+impl DtCustom {
+    pub fn new(factory: &'static dyn GeneratorFactory) -> DtCustom {
+        DtCustom(factory)
+    }
+
+    pub fn factory(&self) -> &'static dyn GeneratorFactory {
+        self.0
+    }
+}
+
+impl PartialEq for DtCustom {
+    fn eq(&self, other: &DtCustom) -> bool {
+        // The names of the registered generators are unique.
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for DtCustom {}
+
+impl std::fmt::Debug for DtCustom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DtCustom({})", self.0.name())
+    }
+}
+
 /// Stream algorithm type.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DtStreamType {
@@ -39,11 +110,377 @@ pub enum DtStreamType {
     ChaCha12,
     ChaCha20,
     Crc,
+    Aes128Ctr,
+    Aes256Ctr,
+    SplitMix,
+    /// All bits zero.
+    Zeros,
+    /// All bits one.
+    Ones,
+    /// 0x55 checkerboard.
+    Checker55,
+    /// 0xAA checkerboard.
+    CheckerAA,
+    /// A single one bit walking through the zero bits of a byte.
+    WalkingOnes,
+    /// A single zero bit walking through the one bits of a byte.
+    WalkingZeros,
+    /// User supplied repeating byte pattern.
+    Pattern(DtPattern),
+    /// ChaCha20 with a self-describing tag at the start of every block.
+    Tagged(DtTagged),
+    /// Generator registered with register_generator().
+    Custom(DtCustom),
+}
+
+impl DtStreamType {
+    /// Get the repeating byte pattern of a deterministic pattern type.
+    /// Returns None for random number generators.
+    pub fn pattern(&self) -> Option<&[u8]> {
+        match self {
+            DtStreamType::ChaCha8
+            | DtStreamType::ChaCha12
+            | DtStreamType::ChaCha20
+            | DtStreamType::Crc
+            | DtStreamType::Aes128Ctr
+            | DtStreamType::Aes256Ctr
+            | DtStreamType::SplitMix
+            | DtStreamType::Tagged(_)
+            | DtStreamType::Custom(_) => None,
+            DtStreamType::Zeros => Some(GeneratorPattern::ZEROS),
+            DtStreamType::Ones => Some(GeneratorPattern::ONES),
+            DtStreamType::Checker55 => Some(GeneratorPattern::CHECKER_55),
+            DtStreamType::CheckerAA => Some(GeneratorPattern::CHECKER_AA),
+            DtStreamType::WalkingOnes => Some(GeneratorPattern::WALKING_ONES),
+            DtStreamType::WalkingZeros => Some(GeneratorPattern::WALKING_ZEROS),
+            DtStreamType::Pattern(pattern) => Some(pattern.as_bytes()),
+        }
+    }
+
+    /// Get the factory of the generator algorithm.
+    pub fn factory(&self) -> Box<dyn GeneratorFactory> {
+        if let Some(factory) = self.random_factory() {
+            return Box::new(factory);
+        }
+        match self {
+            DtStreamType::Tagged(tagged) => Box::new(TaggedFactory {
+                run_id: tagged.run_id,
+                pass: tagged.pass,
+            }),
+            DtStreamType::Custom(custom) => Box::new(custom.factory()),
+            _ => Box::new(PatternFactory::new(
+                &self.to_string(),
+                self.pattern().expect("Stream type has no pattern."),
+            )),
+        }
+    }
+
+    /// Get the factory of a built-in random number generator.
+    /// Returns None for the other stream types.
+    fn random_factory(&self) -> Option<&'static RandomFactory> {
+        match self {
+            DtStreamType::ChaCha8 => Some(&RandomFactory::CHACHA8),
+            DtStreamType::ChaCha12 => Some(&RandomFactory::CHACHA12),
+            DtStreamType::ChaCha20 => Some(&RandomFactory::CHACHA20),
+            DtStreamType::Crc => Some(&RandomFactory::CRC),
+            DtStreamType::Aes128Ctr => Some(&RandomFactory::AES128CTR),
+            DtStreamType::Aes256Ctr => Some(&RandomFactory::AES256CTR),
+            DtStreamType::SplitMix => Some(&RandomFactory::SPLITMIX),
+            _ => None,
+        }
+    }
+
+    /// Get the size of the generator output with count = 1, in bytes.
+    /// This does not construct a factory.
+    pub fn base_size(&self) -> usize {
+        match (self.random_factory(), self) {
+            (Some(factory), _) => factory.base_size(),
+            (None, DtStreamType::Tagged(_)) => GeneratorTagged::BASE_SIZE,
+            (None, DtStreamType::Custom(custom)) => custom.factory().base_size(),
+            (None, _) => GeneratorPattern::base_size(
+                self.pattern().expect("Stream type has no pattern.").len(),
+            ),
+        }
+    }
+
+    /// Get the default chunk size multiplicator of the generator.
+    /// This does not construct a factory.
+    pub fn default_chunk_factor(&self) -> usize {
+        match (self.random_factory(), self) {
+            (Some(factory), _) => factory.default_chunk_factor(),
+            (None, DtStreamType::Tagged(_)) => GeneratorTagged::DEFAULT_CHUNK_FACTOR,
+            (None, DtStreamType::Custom(custom)) => custom.factory().default_chunk_factor(),
+            (None, _) => GeneratorPattern::default_chunk_factor(
+                self.pattern().expect("Stream type has no pattern.").len(),
+            ),
+        }
+    }
+
+    /// Set the run ID and the pass number of a tagged stream.
+    /// Other stream types are returned unchanged.
+    pub fn with_run(self, run_id: u64, pass: u32) -> DtStreamType {
+        match self {
+            DtStreamType::Tagged(_) => DtStreamType::Tagged(DtTagged { run_id, pass }),
+            stype => stype,
+        }
+    }
+}
+
+impl std::fmt::Display for DtStreamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            DtStreamType::ChaCha8 => "CHACHA8",
+            DtStreamType::ChaCha12 => "CHACHA12",
+            DtStreamType::ChaCha20 => "CHACHA20",
+            DtStreamType::Crc => "CRC",
+            DtStreamType::Aes128Ctr => "AES128CTR",
+            DtStreamType::Aes256Ctr => "AES256CTR",
+            DtStreamType::SplitMix => "SPLITMIX",
+            DtStreamType::Zeros => "ZEROS",
+            DtStreamType::Ones => "ONES",
+            DtStreamType::Checker55 => "CHECKER55",
+            DtStreamType::CheckerAA => "CHECKERAA",
+            DtStreamType::WalkingOnes => "WALKING1",
+            DtStreamType::WalkingZeros => "WALKING0",
+            DtStreamType::Tagged(_) => "TAGGED",
+            DtStreamType::Custom(custom) => custom.factory().name(),
+            DtStreamType::Pattern(pattern) => {
+                write!(f, "PATTERN:")?;
+                for x in pattern.as_bytes() {
+                    write!(f, "{:02X}", x)?;
+                }
+                return Ok(());
+            }
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl DtStreamType {
+    /// Parse the name of a built-in algorithm.
+    /// Returns None, if the name is not a built-in algorithm.
+    pub fn parse_builtin(s: &str) -> ah::Result<Option<DtStreamType>> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(hex) = upper.strip_prefix("PATTERN:") {
+            let hex = hex.strip_prefix("0X").unwrap_or(hex);
+            if hex.is_empty() || hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(ah::format_err!(
+                    "Invalid pattern '{}'. Expected an even number of hex digits.",
+                    s
+                ));
+            }
+            let bytes: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+            return Ok(Some(DtStreamType::Pattern(DtPattern::new(&bytes)?)));
+        }
+        Ok(match upper.as_str() {
+            "CHACHA8" => Some(DtStreamType::ChaCha8),
+            "CHACHA12" => Some(DtStreamType::ChaCha12),
+            "CHACHA20" => Some(DtStreamType::ChaCha20),
+            "CRC" => Some(DtStreamType::Crc),
+            "AES128CTR" => Some(DtStreamType::Aes128Ctr),
+            "AES256CTR" => Some(DtStreamType::Aes256Ctr),
+            "SPLITMIX" => Some(DtStreamType::SplitMix),
+            "ZEROS" => Some(DtStreamType::Zeros),
+            "ONES" => Some(DtStreamType::Ones),
+            "CHECKER55" => Some(DtStreamType::Checker55),
+            "CHECKERAA" => Some(DtStreamType::CheckerAA),
+            "WALKING1" => Some(DtStreamType::WalkingOnes),
+            "WALKING0" => Some(DtStreamType::WalkingZeros),
+            "TAGGED" => Some(DtStreamType::Tagged(DtTagged::default())),
+            _ => None,
+        })
+    }
+}
+
+impl std::str::FromStr for DtStreamType {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> ah::Result<DtStreamType> {
+        match DtStreamType::parse_builtin(s)? {
+            Some(stype) => Ok(stype),
+            None => find_generator(s).ok_or_else(|| ah::format_err!("Invalid algorithm '{}'.", s)),
+        }
+    }
+}
+
+/// Layout of the aggregated stream.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DtStreamLayout {
+    /// Round-robin interleaving of independent per-thread streams.
+    /// The stream depends on the number of threads.
+    #[default]
+    Interleaved,
+    /// Every chunk only depends on the seed and the chunk index.
+    /// The stream is the same for any number of threads
+    /// and equal to the interleaved stream of a single thread.
+    Indexed,
+}
+
+impl DtStreamLayout {
+    /// Get the distance between the chunks of the aggregated stream,
+    /// that are generated by the same generator.
+    pub fn stride(&self, num_threads: usize) -> u64 {
+        match self {
+            DtStreamLayout::Interleaved => num_threads as u64,
+            DtStreamLayout::Indexed => 1,
+        }
+    }
+
+    /// Get the number of generator keys.
+    pub fn key_count(&self, num_threads: usize) -> u32 {
+        match self {
+            DtStreamLayout::Interleaved => num_threads as u32,
+            DtStreamLayout::Indexed => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for DtStreamLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DtStreamLayout::Interleaved => write!(f, "INTERLEAVED"),
+            DtStreamLayout::Indexed => write!(f, "INDEXED"),
+        }
+    }
+}
+
+impl std::str::FromStr for DtStreamLayout {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> ah::Result<DtStreamLayout> {
+        match s.to_ascii_uppercase().as_str() {
+            "INTERLEAVED" => Ok(DtStreamLayout::Interleaved),
+            "INDEXED" => Ok(DtStreamLayout::Indexed),
+            _ => Err(ah::format_err!("Invalid stream layout '{}'.", s)),
+        }
+    }
+}
+
+/// Key schedule, that derives the generator keys from the seed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DtKeySchedule {
+    /// Every thread derives its key from the seed with the slow KDF.
+    /// The startup time grows with the number of threads.
+    #[default]
+    V1,
+    /// The slow KDF derives one master key from the seed
+    /// and the thread keys are cheaply expanded from it with HKDF.
+    V2,
+}
+
+impl std::fmt::Display for DtKeySchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DtKeySchedule::V1 => write!(f, "V1"),
+            DtKeySchedule::V2 => write!(f, "V2"),
+        }
+    }
+}
+
+impl std::str::FromStr for DtKeySchedule {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> ah::Result<DtKeySchedule> {
+        match s.to_ascii_uppercase().as_str() {
+            "V1" => Ok(DtKeySchedule::V1),
+            "V2" => Ok(DtKeySchedule::V2),
+            _ => Err(ah::format_err!("Invalid key schedule '{}'.", s)),
+        }
+    }
+}
+
+/// Target compression ratio of the generated data, in hundredths.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DtCompressRatio(u32);
+
+impl DtCompressRatio {
+    /// Incompressible data.
+    pub const NONE: DtCompressRatio = DtCompressRatio(100);
+
+    pub fn as_f64(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+}
+
+impl Default for DtCompressRatio {
+    fn default() -> DtCompressRatio {
+        DtCompressRatio::NONE
+    }
+}
+
+impl std::fmt::Display for DtCompressRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+impl std::str::FromStr for DtCompressRatio {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> ah::Result<DtCompressRatio> {
+        match s.trim().parse::<f64>() {
+            Ok(ratio) if (1.0..=GeneratorCompress::MAX_RATIO).contains(&ratio) => {
+                Ok(DtCompressRatio((ratio * 100.0).round() as u32))
+            }
+            _ => Err(ah::format_err!(
+                "Invalid compression ratio '{}'. Expected a number between 1 and {}.",
+                s,
+                GeneratorCompress::MAX_RATIO
+            )),
+        }
+    }
+}
+
+/// Target deduplication ratio of the generated data, in hundredths.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DtDedupRatio(u32);
+
+impl DtDedupRatio {
+    /// No duplicate chunks.
+    pub const NONE: DtDedupRatio = DtDedupRatio(100);
+
+    pub fn as_f64(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+}
+
+impl Default for DtDedupRatio {
+    fn default() -> DtDedupRatio {
+        DtDedupRatio::NONE
+    }
+}
+
+impl std::fmt::Display for DtDedupRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+impl std::str::FromStr for DtDedupRatio {
+    type Err = ah::Error;
+
+    fn from_str(s: &str) -> ah::Result<DtDedupRatio> {
+        match s.trim().parse::<f64>() {
+            Ok(ratio) if (1.0..=DedupMap::MAX_RATIO).contains(&ratio) => {
+                Ok(DtDedupRatio((ratio * 100.0).round() as u32))
+            }
+            _ => Err(ah::format_err!(
+                "Invalid deduplication ratio '{}'. Expected a number between 1 and {}.",
+                s,
+                DedupMap::MAX_RATIO
+            )),
+        }
+    }
 }
 
 /// Data chunk that contains the computed PRNG data.
 pub struct DtStreamChunk {
     pub data: Option<Vec<u8>>,
+    /// Sequence number of the chunk, to check the order in tests.
+    #[cfg(test)]
     pub index: u8,
 }
 
@@ -51,9 +488,13 @@ pub struct DtStreamChunk {
 #[allow(clippy::too_many_arguments)]
 fn thread_worker(
     stype: DtStreamType,
+    layout: DtStreamLayout,
+    compress: DtCompressRatio,
+    dedup_map: Option<DedupMap>,
     chunk_factor: usize,
-    seed: Vec<u8>,
+    keys: Arc<DtKeySet>,
     thread_id: u32,
+    num_threads: u32,
     mut cache_cons: BufCacheCons,
     byte_offset: u64,
     invert_pattern: bool,
@@ -63,17 +504,37 @@ fn thread_worker(
     sleep: Arc<(Mutex<bool>, Condvar)>,
     tx: Sender<DtStreamChunk>,
 ) {
-    // Calculate the per-thread-seed from the global seed.
-    let thread_seed = kdf(&seed, thread_id);
-    drop(seed);
+    // In the indexed layout all threads compute chunks of one common stream.
+    // That is the stream of the first thread of the interleaved layout.
+    let (key_id, stream_id, stream_count) = match layout {
+        DtStreamLayout::Interleaved => (thread_id, thread_id, num_threads),
+        DtStreamLayout::Indexed => (0, 0, 1),
+    };
+
+    // The per-thread-seed has already been derived from the global seed.
+    let thread_seed = keys.key(key_id);
+    let stride = layout.stride(num_threads as usize);
 
     // Construct the generator algorithm.
-    let mut generator: Box<dyn NextRandom> = match stype {
-        DtStreamType::ChaCha8 => Box::new(GeneratorChaCha8::new(&thread_seed)),
-        DtStreamType::ChaCha12 => Box::new(GeneratorChaCha12::new(&thread_seed)),
-        DtStreamType::ChaCha20 => Box::new(GeneratorChaCha20::new(&thread_seed)),
-        DtStreamType::Crc => Box::new(GeneratorCrc::new(&thread_seed)),
-    };
+    let mut generator = stype.factory().new_generator(&GeneratorParams {
+        key: thread_seed,
+        stream_id,
+        stream_count,
+        chunk_factor,
+    });
+
+    // Make the data compressible, if requested.
+    if compress != DtCompressRatio::NONE {
+        generator = Box::new(GeneratorCompress::new(
+            generator,
+            thread_seed,
+            compress.as_f64(),
+        ));
+    }
+
+    // The chunk index in the aggregated stream.
+    let size = generator.get_base_size() * chunk_factor;
+    let mut chunk_index = (byte_offset / size as u64) * num_threads as u64 + thread_id as u64;
 
     // Seek the generator to the specified byte offset.
     if let Err(e) = generator.seek(byte_offset) {
@@ -83,12 +544,26 @@ fn thread_worker(
     }
 
     // Run the generator work loop.
+    #[cfg(test)]
     let mut index = 0;
     let mut cur_level = level.load(Ordering::Relaxed);
     while !abort.load(Ordering::SeqCst) {
         if cur_level < DtStream::MAX_THRES {
+            // Seek to the chunk of the common stream, in the indexed layout,
+            // and to the chunk with the unique data of a duplicate chunk.
+            if layout == DtStreamLayout::Indexed || dedup_map.is_some() {
+                let source = dedup_map
+                    .as_ref()
+                    .map_or(chunk_index, |map| map.source(chunk_index));
+                if let Err(e) = generator.seek((source / stride) * size as u64) {
+                    eprintln!("ERROR in generator thread {}: {}", thread_id, e);
+                    error.store(true, Ordering::Relaxed);
+                    return;
+                }
+            }
+            chunk_index += num_threads as u64;
+
             // Get the next chunk from the generator.
-            let size = generator.get_base_size() * chunk_factor;
             let mut data = cache_cons.pull(size);
             generator.next(&mut data, chunk_factor);
             debug_assert_eq!(data.len(), size);
@@ -102,9 +577,13 @@ fn thread_worker(
 
             let chunk = DtStreamChunk {
                 data: Some(data),
+                #[cfg(test)]
                 index,
             };
-            index = index.wrapping_add(1);
+            #[cfg(test)]
+            {
+                index = index.wrapping_add(1);
+            }
 
             // Send the chunk to the main thread.
             tx.send(chunk).expect("Worker thread: Send failed.");
@@ -124,9 +603,16 @@ fn thread_worker(
 /// PRNG stream.
 pub struct DtStream {
     stype: DtStreamType,
-    seed: Vec<u8>,
+    layout: DtStreamLayout,
+    compress: DtCompressRatio,
+    dedup: DtDedupRatio,
+    key_schedule: DtKeySchedule,
+    seed: Arc<DtSecret>,
+    /// The derived generator keys.
+    keys: Option<Arc<DtKeySet>>,
     invert_pattern: bool,
     thread_id: u32,
+    num_threads: u32,
     rx: Option<Receiver<DtStreamChunk>>,
     cache: Rc<RefCell<BufCache>>,
     is_active: bool,
@@ -145,9 +631,10 @@ impl DtStream {
 
     pub fn new(
         stype: DtStreamType,
-        seed: Vec<u8>,
+        seed: Arc<DtSecret>,
         invert_pattern: bool,
         thread_id: u32,
+        num_threads: u32,
         cache: Rc<RefCell<BufCache>>,
     ) -> DtStream {
         let abort = Arc::new(AtomicBool::new(false));
@@ -156,9 +643,15 @@ impl DtStream {
         let sleep = Arc::new((Mutex::new(false), Condvar::new()));
         DtStream {
             stype,
+            layout: DtStreamLayout::Interleaved,
+            compress: DtCompressRatio::NONE,
+            dedup: DtDedupRatio::NONE,
+            key_schedule: DtKeySchedule::V1,
             seed,
+            keys: None,
             invert_pattern,
             thread_id,
+            num_threads,
             rx: None,
             cache,
             is_active: false,
@@ -170,6 +663,36 @@ impl DtStream {
         }
     }
 
+    /// Set the stream layout.
+    /// It takes effect at the next activation.
+    pub fn set_layout(&mut self, layout: DtStreamLayout) {
+        self.layout = layout;
+    }
+
+    /// Set the target compression ratio of the generated data.
+    /// It takes effect at the next activation.
+    pub fn set_compress_ratio(&mut self, compress: DtCompressRatio) {
+        self.compress = compress;
+    }
+
+    /// Set the target deduplication ratio of the generated data.
+    /// It takes effect at the next activation.
+    pub fn set_dedup_ratio(&mut self, dedup: DtDedupRatio) {
+        self.dedup = dedup;
+    }
+
+    /// Set the key schedule.
+    /// It takes effect at the next activation.
+    pub fn set_key_schedule(&mut self, key_schedule: DtKeySchedule) {
+        self.key_schedule = key_schedule;
+    }
+
+    /// Set the already derived generator keys.
+    /// Otherwise the keys are derived at the next activation.
+    pub fn set_key_set(&mut self, keys: Arc<DtKeySet>) {
+        self.keys = Some(keys);
+    }
+
     /// Wake up the worker thread, if it is currently sleeping.
     fn wake_thread(&self) {
         let mut sleeping = self.sleep.0.lock().expect("Wake Condvar lock poison");
@@ -206,9 +729,21 @@ impl DtStream {
 
         // Spawn the worker thread.
         let thread_stype = self.stype;
+        let thread_layout = self.layout;
+        let thread_compress = self.compress;
         let thread_chunk_factor = chunk_factor;
-        let thread_seed = self.seed.to_vec();
+        let key_count = self.layout.key_count(self.num_threads as usize);
+        let thread_keys =
+            DtKeySet::get_or_derive(&mut self.keys, &self.seed, self.key_schedule, key_count);
+        let thread_dedup_map = (self.dedup != DtDedupRatio::NONE).then(|| {
+            DedupMap::new(
+                &thread_keys,
+                self.dedup.as_f64(),
+                self.layout.stride(self.num_threads as usize),
+            )
+        });
         let thread_id = self.thread_id;
+        let thread_num_threads = self.num_threads;
         let thread_cache_cons = self.cache.borrow_mut().new_consumer(self.thread_id);
         let thread_byte_offset = byte_offset;
         let thread_invert_pattern = self.invert_pattern;
@@ -219,9 +754,13 @@ impl DtStream {
         self.thread_join = Some(thread::spawn(move || {
             thread_worker(
                 thread_stype,
+                thread_layout,
+                thread_compress,
+                thread_dedup_map,
                 thread_chunk_factor,
-                thread_seed,
+                thread_keys,
                 thread_id,
+                thread_num_threads,
                 thread_cache_cons,
                 thread_byte_offset,
                 thread_invert_pattern,
@@ -257,22 +796,12 @@ impl DtStream {
 
     /// Get the chunk base size.
     pub fn get_chunk_size(&self) -> usize {
-        match self.stype {
-            DtStreamType::ChaCha8 => GeneratorChaCha8::BASE_SIZE,
-            DtStreamType::ChaCha12 => GeneratorChaCha12::BASE_SIZE,
-            DtStreamType::ChaCha20 => GeneratorChaCha20::BASE_SIZE,
-            DtStreamType::Crc => GeneratorCrc::BASE_SIZE,
-        }
+        self.stype.base_size()
     }
 
     /// Get the default chunk factor of the selected generator.
     pub fn get_default_chunk_factor(&self) -> usize {
-        match self.stype {
-            DtStreamType::ChaCha8 => GeneratorChaCha8::DEFAULT_CHUNK_FACTOR,
-            DtStreamType::ChaCha12 => GeneratorChaCha12::DEFAULT_CHUNK_FACTOR,
-            DtStreamType::ChaCha20 => GeneratorChaCha20::DEFAULT_CHUNK_FACTOR,
-            DtStreamType::Crc => GeneratorCrc::DEFAULT_CHUNK_FACTOR,
-        }
+        self.stype.default_chunk_factor()
     }
 
     /// Get the next chunk from the thread.
@@ -280,15 +809,16 @@ impl DtStream {
     #[inline]
     pub fn get_chunk(&mut self) -> ah::Result<Option<DtStreamChunk>> {
         if !self.is_active() {
-            return Err(ah::format_err!("Generator stream is not active."));
+            return Err(DisktestError::GeneratorFailure.msg("Generator stream is not active."));
         }
         if self.is_thread_error() {
-            return Err(ah::format_err!(
-                "Generator stream thread aborted with an error."
-            ));
+            return Err(DisktestError::GeneratorFailure
+                .msg("Generator stream thread aborted with an error."));
         }
         let Some(rx) = &self.rx else {
-            return Err(ah::format_err!("Generator stream RX channel not present."));
+            return Err(
+                DisktestError::GeneratorFailure.msg("Generator stream RX channel not present.")
+            );
         };
         let Ok(chunk) = rx.try_recv() else {
             // Queue is empty. Wake thread.
@@ -330,7 +860,14 @@ mod tests {
     fn run_base_test(algorithm: DtStreamType) {
         println!("stream base test");
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut s = DtStream::new(algorithm, vec![1, 2, 3], false, 0, cache);
+        let mut s = DtStream::new(
+            algorithm,
+            Arc::new(vec![1, 2, 3].into()),
+            false,
+            0,
+            1,
+            cache,
+        );
         s.activate(0, s.get_default_chunk_factor()).unwrap();
         assert!(s.is_active());
 
@@ -363,6 +900,22 @@ mod tests {
             DtStreamType::Crc => {
                 assert_eq!(results_first, vec![108, 99, 114, 196, 213]);
             }
+            DtStreamType::Aes128Ctr => {
+                assert_eq!(results_first, vec![231, 218, 108, 105, 186]);
+            }
+            DtStreamType::Aes256Ctr => {
+                assert_eq!(results_first, vec![177, 44, 111, 110, 155]);
+            }
+            DtStreamType::SplitMix => {
+                assert_eq!(results_first, vec![78, 8, 111, 55, 216]);
+            }
+            DtStreamType::Tagged(_) => {
+                assert_eq!(results_first, vec![b'D'; 5]);
+            }
+            _ => {
+                let first = algorithm.pattern().unwrap()[0];
+                assert_eq!(results_first, vec![first; 5]);
+            }
         }
     }
 
@@ -370,12 +923,26 @@ mod tests {
         println!("stream offset test");
         // a: start at chunk offset 0
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, cache);
+        let mut a = DtStream::new(
+            algorithm,
+            Arc::new(vec![1, 2, 3].into()),
+            false,
+            0,
+            1,
+            cache,
+        );
         a.activate(0, a.get_default_chunk_factor()).unwrap();
 
         // b: start at chunk offset 1
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut b = DtStream::new(algorithm, vec![1, 2, 3], false, 0, cache);
+        let mut b = DtStream::new(
+            algorithm,
+            Arc::new(vec![1, 2, 3].into()),
+            false,
+            0,
+            1,
+            cache,
+        );
         b.activate(
             a.get_chunk_size() as u64 * a.get_default_chunk_factor() as u64,
             a.get_default_chunk_factor(),
@@ -392,10 +959,17 @@ mod tests {
     fn run_invert_test(algorithm: DtStreamType) {
         println!("stream invert test");
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut a = DtStream::new(algorithm, vec![1, 2, 3], false, 0, cache);
+        let mut a = DtStream::new(
+            algorithm,
+            Arc::new(vec![1, 2, 3].into()),
+            false,
+            0,
+            1,
+            cache,
+        );
         a.activate(0, a.get_default_chunk_factor()).unwrap();
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
-        let mut b = DtStream::new(algorithm, vec![1, 2, 3], true, 0, cache);
+        let mut b = DtStream::new(algorithm, Arc::new(vec![1, 2, 3].into()), true, 0, 1, cache);
         b.activate(0, a.get_default_chunk_factor()).unwrap();
 
         let achunk = a.wait_chunk();
@@ -411,6 +985,21 @@ mod tests {
         assert!(achunk.data.as_ref().unwrap() == &inv_bchunk);
     }
 
+    #[test]
+    fn test_base_size() {
+        for stype in [
+            DtStreamType::ChaCha20,
+            DtStreamType::SplitMix,
+            DtStreamType::Tagged(DtTagged::default()),
+            DtStreamType::WalkingOnes,
+            DtStreamType::Pattern(DtPattern::new(&[1, 2, 3]).unwrap()),
+        ] {
+            let factory = stype.factory();
+            assert_eq!(stype.base_size(), factory.base_size());
+            assert_eq!(stype.default_chunk_factor(), factory.default_chunk_factor());
+        }
+    }
+
     #[test]
     fn test_chacha8() {
         let alg = DtStreamType::ChaCha8;
@@ -442,6 +1031,86 @@ mod tests {
         run_offset_test(alg);
         run_invert_test(alg);
     }
+
+    #[test]
+    fn test_aes128ctr() {
+        let alg = DtStreamType::Aes128Ctr;
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_aes256ctr() {
+        let alg = DtStreamType::Aes256Ctr;
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_splitmix() {
+        let alg = DtStreamType::SplitMix;
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_tagged() {
+        let alg: DtStreamType = "tagged".parse().unwrap();
+        assert_eq!(alg.to_string(), "TAGGED");
+        let alg = alg.with_run(42, 1);
+        assert_eq!(
+            alg,
+            DtStreamType::Tagged(DtTagged {
+                run_id: 42,
+                pass: 1
+            })
+        );
+        assert_eq!(DtStreamType::Crc.with_run(42, 1), DtStreamType::Crc);
+        run_base_test(alg);
+        run_offset_test(alg);
+        run_invert_test(alg);
+    }
+
+    #[test]
+    fn test_patterns() {
+        for name in [
+            "ZEROS",
+            "ONES",
+            "CHECKER55",
+            "CHECKERAA",
+            "WALKING1",
+            "WALKING0",
+            "PATTERN:DEADBEEF01",
+        ] {
+            let alg: DtStreamType = name.parse().unwrap();
+            assert_eq!(alg.to_string(), name);
+            run_base_test(alg);
+
+            // Seeking by one chunk results in the same data.
+            let pattern = alg.pattern().unwrap();
+            let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
+            let mut a = DtStream::new(alg, Arc::new(vec![1, 2, 3].into()), false, 0, 1, cache);
+            let chunk_size = a.get_chunk_size() * a.get_default_chunk_factor();
+            assert_eq!(chunk_size % pattern.len(), 0);
+            a.activate(chunk_size as u64, a.get_default_chunk_factor())
+                .unwrap();
+            let data = a.wait_chunk().data.unwrap();
+            assert_eq!(data.len(), chunk_size);
+            assert!(data.iter().zip(pattern.iter().cycle()).all(|(a, b)| a == b));
+        }
+
+        let alg: DtStreamType = "pattern:0x00ff".parse().unwrap();
+        assert_eq!(alg.pattern(), Some(&[0x00, 0xFF][..]));
+        assert!("PATTERN:".parse::<DtStreamType>().is_err());
+        assert!("PATTERN:ABC".parse::<DtStreamType>().is_err());
+        assert!("PATTERN:XY".parse::<DtStreamType>().is_err());
+        assert!(format!("PATTERN:{}", "00".repeat(DtPattern::MAX_LEN + 1))
+            .parse::<DtStreamType>()
+            .is_err());
+    }
 }
 
 // vim: ts=4 sw=4 expandtab
@@ -20,28 +20,36 @@
 //
 
 use crate::bufcache::BufCache;
+use crate::dedup::DedupMap;
 use crate::disktest::DisktestQuiet;
-use crate::stream::{DtStream, DtStreamChunk};
-use crate::util::prettybytes;
+use crate::kdf::DtKeySet;
+use crate::secret::DtSecret;
+use crate::stream::{
+    DtCompressRatio, DtDedupRatio, DtKeySchedule, DtStream, DtStreamChunk, DtStreamLayout,
+};
 use anyhow as ah;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub use crate::stream::DtStreamType;
 
 pub struct DtStreamAggChunk {
     chunk: DtStreamChunk,
+    /// Number of discarded bytes at the start of the chunk.
+    skip: usize,
     thread_id: usize,
     cache: Rc<RefCell<BufCache>>,
 }
 
 impl DtStreamAggChunk {
     pub fn get_data(&self) -> &[u8] {
-        self.chunk
+        &self
+            .chunk
             .data
             .as_ref()
-            .expect("DtStreamChunk data was None before drop!")
+            .expect("DtStreamChunk data was None before drop!")[self.skip..]
     }
 }
 
@@ -63,10 +71,21 @@ pub struct DtStreamAggActivateResult {
 }
 
 pub struct DtStreamAgg {
+    stype: DtStreamType,
+    layout: DtStreamLayout,
+    compress: DtCompressRatio,
+    dedup: DtDedupRatio,
+    key_schedule: DtKeySchedule,
+    seed: Arc<DtSecret>,
+    /// The derived generator keys, once they are derived.
+    keys: Option<Arc<DtKeySet>>,
+    invert_pattern: bool,
     num_threads: usize,
     streams: Vec<DtStream>,
     cache: Rc<RefCell<BufCache>>,
     current_index: usize,
+    /// Number of bytes to discard from the next chunk.
+    skip: usize,
     is_active: bool,
     quiet_level: DisktestQuiet,
 }
@@ -74,44 +93,151 @@ pub struct DtStreamAgg {
 impl DtStreamAgg {
     pub fn new(
         stype: DtStreamType,
-        seed: Vec<u8>,
+        seed: impl Into<DtSecret>,
+        invert_pattern: bool,
+        num_threads: usize,
+        quiet_level: DisktestQuiet,
+    ) -> DtStreamAgg {
+        let seed = Arc::new(seed.into());
+        DtStreamAgg::with_shared_seed(stype, seed, invert_pattern, num_threads, quiet_level)
+    }
+
+    /// Create a new stream aggregator, that shares the seed with other instances.
+    fn with_shared_seed(
+        stype: DtStreamType,
+        seed: Arc<DtSecret>,
         invert_pattern: bool,
         num_threads: usize,
         quiet_level: DisktestQuiet,
     ) -> DtStreamAgg {
         assert!(num_threads > 0);
-        assert!(num_threads <= std::u16::MAX as usize + 1);
+        assert!(num_threads <= u16::MAX as usize + 1);
 
         let cache = Rc::new(RefCell::new(BufCache::new(DisktestQuiet::Normal)));
         let mut streams = Vec::with_capacity(num_threads);
         for i in 0..num_threads {
             let stream = DtStream::new(
                 stype,
-                seed.to_vec(),
+                Arc::clone(&seed),
                 invert_pattern,
                 i as u32,
+                num_threads as u32,
                 Rc::clone(&cache),
             );
             streams.push(stream);
         }
 
         DtStreamAgg {
+            stype,
+            layout: DtStreamLayout::Interleaved,
+            compress: DtCompressRatio::NONE,
+            dedup: DtDedupRatio::NONE,
+            key_schedule: DtKeySchedule::V1,
+            seed,
+            keys: None,
+            invert_pattern,
             num_threads,
             streams,
             cache,
             current_index: 0,
+            skip: 0,
             is_active: false,
             quiet_level,
         }
     }
 
+    /// Create a new inactive stream aggregator with the same stream parameters.
+    pub fn duplicate(&self) -> DtStreamAgg {
+        let mut agg = DtStreamAgg::with_shared_seed(
+            self.stype,
+            Arc::clone(&self.seed),
+            self.invert_pattern,
+            self.num_threads,
+            self.quiet_level,
+        );
+        agg.set_layout(self.layout);
+        agg.set_compress_ratio(self.compress);
+        agg.set_dedup_ratio(self.dedup);
+        agg.set_key_schedule(self.key_schedule);
+        agg.keys = self.keys.clone();
+        agg
+    }
+
+    /// Set the stream layout.
+    /// It takes effect at the next activation.
+    pub fn set_layout(&mut self, layout: DtStreamLayout) {
+        self.layout = layout;
+        for stream in &mut self.streams {
+            stream.set_layout(layout);
+        }
+    }
+
+    /// Set the target compression ratio of the generated data.
+    /// It takes effect at the next activation.
+    pub fn set_compress_ratio(&mut self, compress: DtCompressRatio) {
+        self.compress = compress;
+        for stream in &mut self.streams {
+            stream.set_compress_ratio(compress);
+        }
+    }
+
+    /// Set the target deduplication ratio of the generated data.
+    /// It takes effect at the next activation.
+    pub fn set_dedup_ratio(&mut self, dedup: DtDedupRatio) {
+        self.dedup = dedup;
+        for stream in &mut self.streams {
+            stream.set_dedup_ratio(dedup);
+        }
+    }
+
+    /// Set the key schedule.
+    /// It takes effect at the next activation.
+    pub fn set_key_schedule(&mut self, key_schedule: DtKeySchedule) {
+        self.key_schedule = key_schedule;
+        for stream in &mut self.streams {
+            stream.set_key_schedule(key_schedule);
+        }
+    }
+
+    /// Set the already derived generator keys.
+    /// They are only used, if they match the seed and the stream parameters.
+    pub fn set_key_set(&mut self, keys: Arc<DtKeySet>) {
+        self.keys = Some(keys);
+    }
+
+    /// Get the generator keys, if they have already been derived.
+    pub fn get_key_set(&self) -> Option<Arc<DtKeySet>> {
+        self.keys.clone()
+    }
+
+    /// Get the map of the duplicate chunks.
+    /// Returns None, if the stream has no duplicate chunks.
+    /// Derives the generator keys, if they have not been derived, yet.
+    pub fn get_dedup_map(&mut self) -> Option<DedupMap> {
+        if self.dedup == DtDedupRatio::NONE {
+            return None;
+        }
+        let keys = self.derive_keys();
+        Some(DedupMap::new(
+            &keys,
+            self.dedup.as_f64(),
+            self.layout.stride(self.num_threads),
+        ))
+    }
+
+    /// Derive the generator keys only once for all streams and activations.
+    fn derive_keys(&mut self) -> Arc<DtKeySet> {
+        let key_count = self.layout.key_count(self.num_threads);
+        DtKeySet::get_or_derive(&mut self.keys, &self.seed, self.key_schedule, key_count)
+    }
+
     fn calc_chunk_size(&self, sector_size: u32) -> ah::Result<(u64, u64)> {
         let chunk_factor = self.get_default_chunk_factor() as u64;
         let base_chunk_size = self.get_chunk_size() as u64;
 
         let chunk_size = base_chunk_size * chunk_factor;
 
-        if chunk_size % sector_size as u64 != 0 {
+        if !chunk_size.is_multiple_of(sector_size as u64) {
             return Err(ah::format_err!(
                 "The random number generator chunk size {} \
                  is not a multiple of the disk sector size {}.",
@@ -125,29 +251,23 @@ impl DtStreamAgg {
 
     pub fn activate(
         &mut self,
-        mut byte_offset: u64,
+        byte_offset: u64,
         sector_size: u32,
     ) -> ah::Result<DtStreamAggActivateResult> {
         let (chunk_size, chunk_factor) = self.calc_chunk_size(sector_size)?;
 
         // Calculate the stream index from the byte_offset.
-        if byte_offset % chunk_size != 0 {
-            let good_offset = byte_offset - (byte_offset % chunk_size);
-            if self.quiet_level < DisktestQuiet::NoWarn {
-                eprintln!(
-                    "WARNING: The seek offset {} is not a multiple \
-                    of the random stream generator chunk size {}. \n\
-                    The seek offset will be adjusted to {}.",
-                    prettybytes(byte_offset, true, true, true),
-                    prettybytes(chunk_size, true, true, true),
-                    prettybytes(good_offset, true, true, true)
-                );
-            }
-            byte_offset = good_offset;
-        }
+        // The streams start at the chunk containing byte_offset
+        // and the bytes before byte_offset are discarded from the first chunk.
         let chunk_index = byte_offset / chunk_size;
+        self.skip = (byte_offset % chunk_size) as usize;
         self.current_index = (chunk_index % self.num_threads as u64) as usize;
 
+        let keys = self.derive_keys();
+        for stream in &mut self.streams {
+            stream.set_key_set(Arc::clone(&keys));
+        }
+
         // Calculate the per stream byte offset and activate all streams.
         for (i, stream) in self.streams.iter_mut().enumerate() {
             let iteration = chunk_index / self.num_threads as u64;
@@ -194,6 +314,7 @@ impl DtStreamAgg {
 
         Ok(Some(DtStreamAggChunk {
             chunk,
+            skip: std::mem::take(&mut self.skip),
             thread_id: self.current_index,
             cache: Rc::clone(&self.cache),
         }))
@@ -330,6 +451,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_indexed_layout() {
+        let mk_agg = |num_threads, layout| {
+            let mut agg = DtStreamAgg::new(
+                DtStreamType::ChaCha8,
+                vec![1, 2, 3],
+                false,
+                num_threads,
+                DisktestQuiet::Normal,
+            );
+            agg.set_layout(layout);
+            agg
+        };
+        let collect = |agg: &mut DtStreamAgg, offset| {
+            agg.activate(offset, 512).unwrap();
+            (0..7)
+                .map(|_| agg.wait_chunk().unwrap().get_data().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        // The indexed stream is the interleaved stream of one thread.
+        let mut a = mk_agg(1, DtStreamLayout::Interleaved);
+        let chunk_size = a.activate(0, 512).unwrap().chunk_size;
+        let expected = collect(&mut a, 0);
+        for num_threads in [1, 2, 3] {
+            let mut b = mk_agg(num_threads, DtStreamLayout::Indexed);
+            assert_eq!(collect(&mut b, 0), expected);
+            assert_eq!(collect(&mut b, chunk_size * 2)[..5], expected[2..]);
+            assert_eq!(collect(&mut b.duplicate(), 0), expected);
+        }
+
+        // The interleaved stream depends on the number of threads.
+        let mut c = mk_agg(2, DtStreamLayout::Interleaved);
+        assert_ne!(collect(&mut c, 0), expected);
+    }
+
+    #[test]
+    fn test_dedup() {
+        for (num_threads, layout) in [
+            (1, DtStreamLayout::Interleaved),
+            (3, DtStreamLayout::Interleaved),
+            (2, DtStreamLayout::Indexed),
+        ] {
+            let mut a = DtStreamAgg::new(
+                DtStreamType::SplitMix,
+                vec![1, 2, 3],
+                false,
+                num_threads,
+                DisktestQuiet::Normal,
+            );
+            a.set_layout(layout);
+            a.set_dedup_ratio("4".parse().unwrap());
+            let map = a.get_dedup_map().unwrap();
+            let chunk_size = a.activate(0, 512).unwrap().chunk_size;
+            let chunks: Vec<Vec<u8>> = (0..40)
+                .map(|_| a.wait_chunk().unwrap().get_data().to_vec())
+                .collect();
+
+            // Every chunk holds the data of its source chunk
+            // and only duplicates are equal to each other.
+            let mut duplicates = 0;
+            for (i, chunk) in chunks.iter().enumerate() {
+                let source = map.source(i as u64) as usize;
+                assert_eq!(*chunk, chunks[source]);
+                for (j, other) in chunks.iter().enumerate().take(i) {
+                    assert_eq!(chunk == other, source == map.source(j as u64) as usize);
+                }
+                if source != i {
+                    duplicates += 1;
+                }
+            }
+            assert!(duplicates > 0);
+
+            // The duplicates are reproduced at any offset.
+            let mut b = a.duplicate();
+            b.activate(chunk_size * 17, 512).unwrap();
+            for chunk in &chunks[17..] {
+                assert_eq!(b.wait_chunk().unwrap().get_data(), &chunk[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_schedule() {
+        let mk_agg = |key_schedule| {
+            let mut agg = DtStreamAgg::new(
+                DtStreamType::ChaCha8,
+                vec![1, 2, 3],
+                false,
+                2,
+                DisktestQuiet::Normal,
+            );
+            agg.set_key_schedule(key_schedule);
+            agg
+        };
+        let collect = |agg: &mut DtStreamAgg| {
+            agg.activate(0, 512).unwrap();
+            (0..4)
+                .map(|_| agg.wait_chunk().unwrap().get_data().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let mut a = mk_agg(DtKeySchedule::V2);
+        let expected = collect(&mut a);
+        assert!(a.keys.is_some());
+        assert_ne!(expected[0], expected[1]);
+        assert_eq!(collect(&mut a), expected);
+        assert_eq!(collect(&mut a.duplicate()), expected);
+        assert_eq!(collect(&mut mk_agg(DtKeySchedule::V2)), expected);
+        assert_ne!(collect(&mut mk_agg(DtKeySchedule::V1)), expected);
+
+        // The derived keys are reused by other instances and re-activations.
+        let keys = a.get_key_set().unwrap();
+        let mut b = mk_agg(DtKeySchedule::V2);
+        b.set_key_set(Arc::clone(&keys));
+        assert_eq!(collect(&mut b), expected);
+        assert_eq!(collect(&mut b), expected);
+        assert!(Arc::ptr_eq(&b.get_key_set().unwrap(), &keys));
+
+        // Keys of another key schedule are not used.
+        let mut c = mk_agg(DtKeySchedule::V1);
+        c.set_key_set(Arc::clone(&keys));
+        assert_ne!(collect(&mut c), expected);
+        assert!(!Arc::ptr_eq(&c.get_key_set().unwrap(), &keys));
+    }
+
+    #[test]
+    fn test_unaligned_offset() {
+        let mut a = DtStreamAgg::new(
+            DtStreamType::Crc,
+            vec![1, 2, 3],
+            false,
+            2,
+            DisktestQuiet::Normal,
+        );
+        let chunk_size = a.activate(0, 512).unwrap().chunk_size as usize;
+        let mut expected = vec![];
+        for _ in 0..4 {
+            expected.extend_from_slice(a.wait_chunk().unwrap().get_data());
+        }
+
+        for offset in [512, chunk_size - 512, chunk_size + 1024, chunk_size * 2 + 7] {
+            let mut b = a.duplicate();
+            let res = b.activate(offset as u64, 512).unwrap();
+            assert_eq!(res.byte_offset, offset as u64);
+            let first = b.wait_chunk().unwrap();
+            assert_eq!(first.get_data().len(), chunk_size - offset % chunk_size);
+            let mut actual = first.get_data().to_vec();
+            actual.extend_from_slice(b.wait_chunk().unwrap().get_data());
+            assert_eq!(actual, expected[offset..offset + actual.len()]);
+        }
+    }
+
     #[test]
     fn test_chacha8() {
         let alg = DtStreamType::ChaCha8;
@@ -0,0 +1,115 @@
+// -*- coding: utf-8 -*-
+//
+// disktest - Hard drive tester
+//
+// Copyright 2020-2023 Michael Buesch <m@bues.ch>
+//
+// This program is free software; you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation; either version 2 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along
+// with this program; if not, write to the Free Software Foundation, Inc.,
+// 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+//
+
+use crc::crc32;
+
+/// Size of a tagged block. Every tagged block starts with a tag.
+pub const TAG_BLOCK_SIZE: usize = 512;
+/// Size of the tag at the start of a tagged block.
+pub const TAG_SIZE: usize = 32;
+
+const TAG_MAGIC: [u8; 8] = *b"DTSECTAG";
+/// Size of the tag without the checksum.
+const TAG_DATA_SIZE: usize = TAG_SIZE - 4;
+
+/// Self-describing header of a tagged block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SectorTag {
+    /// Identifies the run (seed) that wrote the block.
+    pub run_id: u64,
+    /// Absolute address of the block, in units of TAG_BLOCK_SIZE.
+    pub lba: u64,
+    /// The pass number that wrote the block.
+    pub pass: u32,
+}
+
+impl SectorTag {
+    /// Write the tag to the start of buf.
+    pub fn encode(&self, buf: &mut [u8]) {
+        let tag = &mut buf[..TAG_SIZE];
+        tag[0..8].copy_from_slice(&TAG_MAGIC);
+        tag[8..16].copy_from_slice(&self.run_id.to_le_bytes());
+        tag[16..24].copy_from_slice(&self.lba.to_le_bytes());
+        tag[24..28].copy_from_slice(&self.pass.to_le_bytes());
+        let crc = crc32::checksum_ieee(&tag[..TAG_DATA_SIZE]);
+        tag[TAG_DATA_SIZE..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Read the tag from the start of buf.
+    /// Returns the tag and whether it is bit inverted.
+    /// Returns None, if buf does not start with a valid tag.
+    pub fn decode(buf: &[u8]) -> Option<(SectorTag, bool)> {
+        if buf.len() < TAG_SIZE {
+            return None;
+        }
+        let mut tag = [0u8; TAG_SIZE];
+        tag.copy_from_slice(&buf[..TAG_SIZE]);
+        let inverted = tag[..8] != TAG_MAGIC;
+        if inverted {
+            for x in &mut tag {
+                *x ^= 0xFF;
+            }
+            if tag[..8] != TAG_MAGIC {
+                return None;
+            }
+        }
+        let crc = crc32::checksum_ieee(&tag[..TAG_DATA_SIZE]);
+        if tag[TAG_DATA_SIZE..] != crc.to_le_bytes() {
+            return None;
+        }
+        Some((
+            SectorTag {
+                run_id: u64::from_le_bytes(tag[8..16].try_into().unwrap()),
+                lba: u64::from_le_bytes(tag[16..24].try_into().unwrap()),
+                pass: u32::from_le_bytes(tag[24..28].try_into().unwrap()),
+            },
+            inverted,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let tag = SectorTag {
+            run_id: 0x0123456789ABCDEF,
+            lba: 42,
+            pass: 3,
+        };
+        let mut buf = [0u8; TAG_BLOCK_SIZE];
+        tag.encode(&mut buf);
+        assert_eq!(&buf[..8], b"DTSECTAG");
+        assert_eq!(SectorTag::decode(&buf), Some((tag, false)));
+        for x in &mut buf {
+            *x ^= 0xFF;
+        }
+        assert_eq!(SectorTag::decode(&buf), Some((tag, true)));
+        buf[20] ^= 0x01;
+        assert_eq!(SectorTag::decode(&buf), None);
+        assert_eq!(SectorTag::decode(&[0u8; TAG_BLOCK_SIZE]), None);
+        assert_eq!(SectorTag::decode(&buf[..TAG_SIZE - 1]), None);
+    }
+}
+
+// vim: ts=4 sw=4 expandtab
@@ -19,6 +19,7 @@
 // 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 //
 
+use crate::secret::DtSecret;
 use anyhow as ah;
 use std::fmt::Write;
 use std::time::Duration;
@@ -196,8 +197,9 @@ impl Hhmmss for Duration {
 /// Fold a byte vector into a smaller byte vector using XOR operation.
 /// If output_size is bigger than input.len(), the trailing bytes
 /// will be filled with zeros.
-pub fn fold(input: &[u8], output_size: usize) -> Vec<u8> {
-    let mut output = vec![0; output_size];
+/// The input usually is a seed, so the output is kept in a secret buffer.
+pub fn fold(input: &[u8], output_size: usize) -> DtSecret {
+    let mut output = DtSecret::new(output_size);
 
     if output_size > 0 {
         for (i, data) in input.iter().enumerate() {
@@ -403,20 +405,23 @@ mod tests {
 
     #[test]
     fn test_fold() {
-        assert_eq!(fold(&[0x55, 0x55, 0xAA, 0xAA], 2), [0xFF, 0xFF]);
-        assert_eq!(fold(&[0x55, 0x55, 0x55, 0x55], 2), [0x00, 0x00]);
-        assert_eq!(fold(&[0x55, 0x55, 0xAA, 0x55], 2), [0xFF, 0x00]);
-        assert_eq!(fold(&[0x55, 0x55, 0x55, 0xAA], 2), [0x00, 0xFF]);
+        assert_eq!(*fold(&[0x55, 0x55, 0xAA, 0xAA], 2), [0xFF, 0xFF]);
+        assert_eq!(*fold(&[0x55, 0x55, 0x55, 0x55], 2), [0x00, 0x00]);
+        assert_eq!(*fold(&[0x55, 0x55, 0xAA, 0x55], 2), [0xFF, 0x00]);
+        assert_eq!(*fold(&[0x55, 0x55, 0x55, 0xAA], 2), [0x00, 0xFF]);
         assert_eq!(
-            fold(&[0x98, 0xB1, 0x5B, 0x47, 0x8F, 0xF7, 0x9C, 0x6F], 3),
+            *fold(&[0x98, 0xB1, 0x5B, 0x47, 0x8F, 0xF7, 0x9C, 0x6F], 3),
             [0x43, 0x51, 0xAC]
         );
-        assert_eq!(fold(&[0x12, 0x34, 0x56, 0x78], 4), [0x12, 0x34, 0x56, 0x78]);
         assert_eq!(
-            fold(&[0x12, 0x34, 0x56, 0x78], 6),
+            *fold(&[0x12, 0x34, 0x56, 0x78], 4),
+            [0x12, 0x34, 0x56, 0x78]
+        );
+        assert_eq!(
+            *fold(&[0x12, 0x34, 0x56, 0x78], 6),
             [0x12, 0x34, 0x56, 0x78, 0x00, 0x00]
         );
-        assert_eq!(fold(&[0x12, 0x34, 0x56, 0x78], 0), []);
+        assert_eq!(*fold(&[0x12, 0x34, 0x56, 0x78], 0), [0u8; 0]);
     }
 }
 